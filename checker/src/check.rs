@@ -0,0 +1,155 @@
+use std::collections::{BTreeMap, HashSet};
+
+use thiserror::Error;
+
+use model::committee::Id;
+
+use crate::log::NodeLog;
+
+/// A safety or liveness property broken by the commit logs. Positions are indexes in the sequence
+/// of vertices committed by the node.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    #[error("Nodes {0} and {1} committed different vertices at position {2}")]
+    Divergence(Id, Id, usize),
+
+    #[error("Node {0} delivered vertex {2} a second time at position {1}")]
+    DuplicateDelivery(Id, usize, String),
+
+    #[error("Node {0} committed vertex {2} at position {1}, but it was never broadcast by its owner")]
+    NeverBroadcast(Id, usize, String),
+
+    #[error("Node {0} waited {2} ms before committing position {1} (limit is {3} ms)")]
+    CommitGap(Id, usize, u64, u64),
+}
+
+/// Check that the sequences committed by the nodes are prefixes of one another. If some of them
+/// diverge, the pair of nodes diverging at the lowest position is reported.
+pub fn check_prefix_consistency<T: PartialEq>(sequences: &BTreeMap<Id, Vec<T>>) -> Result<(), Violation> {
+    let mut first_divergence: Option<Violation> = None;
+    let nodes: Vec<_> = sequences.iter().collect();
+    for (i, (first, a)) in nodes.iter().enumerate() {
+        for (second, b) in &nodes[i + 1..] {
+            if let Some(position) = a.iter().zip(b.iter()).position(|(x, y)| x != y) {
+                match first_divergence {
+                    Some(Violation::Divergence(_, _, earliest)) if earliest <= position => {}
+                    _ => first_divergence = Some(Violation::Divergence(**first, **second, position)),
+                }
+            }
+        }
+    }
+    match first_divergence {
+        Some(violation) => Err(violation),
+        None => Ok(()),
+    }
+}
+
+/// Checks the properties of the consensus on the commit logs of all the nodes.
+#[derive(Clone, Debug, Default)]
+pub struct Checker {
+    /// Maximum time between two consecutive commits of a node (in ms), if bounded.
+    pub max_commit_gap: Option<u64>,
+}
+
+impl Checker {
+    /// Return the violations found in the logs, at most one per property and node.
+    pub fn check(&self, logs: &[NodeLog]) -> Vec<Violation> {
+        let mut violations = vec![];
+
+        let sequences = logs
+            .iter()
+            .map(|log| (log.node_id, log.committed.iter().map(|v| &v.hash).collect::<Vec<_>>()))
+            .collect();
+        if let Err(violation) = check_prefix_consistency(&sequences) {
+            violations.push(violation);
+        }
+
+        let broadcast: HashSet<(&String, &String)> = logs
+            .iter()
+            .flat_map(|log| log.broadcast.iter().map(|v| (&v.hash, &v.owner)))
+            .collect();
+
+        for log in logs {
+            let mut delivered = HashSet::new();
+            if let Some(position) = log.committed.iter().position(|v| !delivered.insert(&v.hash)) {
+                violations.push(Violation::DuplicateDelivery(
+                    log.node_id,
+                    position,
+                    log.committed[position].hash.clone(),
+                ));
+            }
+
+            if let Some(position) = log.committed.iter().position(|v| !broadcast.contains(&(&v.hash, &v.owner))) {
+                violations.push(Violation::NeverBroadcast(
+                    log.node_id,
+                    position,
+                    log.committed[position].hash.clone(),
+                ));
+            }
+
+            if let Some(max_gap) = self.max_commit_gap {
+                let gap = log.committed
+                    .windows(2)
+                    .enumerate()
+                    .map(|(i, w)| (i + 1, w[1].time.saturating_sub(w[0].time)))
+                    .find(|(_, gap)| *gap > max_gap);
+                if let Some((position, gap)) = gap {
+                    violations.push(Violation::CommitGap(log.node_id, position, gap, max_gap));
+                }
+            }
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::VertexRecord;
+
+    fn record(name: &str, owner: &str, time: u64) -> VertexRecord {
+        VertexRecord {
+            round: 1,
+            hash: name.to_string(),
+            owner: owner.to_string(),
+            time,
+        }
+    }
+
+    fn log(node_id: Id, broadcast: Vec<VertexRecord>, committed: Vec<VertexRecord>) -> NodeLog {
+        NodeLog { node_id, broadcast, committed }
+    }
+
+    #[test]
+    fn test_consistent_logs() {
+        let logs = vec![
+            log(1, vec![record("a", "1", 0)], vec![record("a", "1", 10), record("b", "2", 20)]),
+            log(2, vec![record("b", "2", 0)], vec![record("a", "1", 15)]),
+        ];
+        assert!(Checker { max_commit_gap: Some(100) }.check(&logs).is_empty());
+    }
+
+    #[test]
+    fn test_first_divergence_is_reported() {
+        let mut sequences = BTreeMap::new();
+        sequences.insert(1, vec!["a", "b", "c"]);
+        sequences.insert(2, vec!["a", "b", "d"]);
+        sequences.insert(3, vec!["a", "c"]);
+        assert_eq!(check_prefix_consistency(&sequences), Err(Violation::Divergence(1, 3, 1)));
+    }
+
+    #[test]
+    fn test_duplicate_invalid_and_gap() {
+        let logs = vec![log(
+            1,
+            vec![record("a", "1", 0)],
+            vec![record("a", "1", 10), record("x", "2", 20), record("a", "1", 500)],
+        )];
+        let violations = Checker { max_commit_gap: Some(100) }.check(&logs);
+        assert_eq!(violations, vec![
+            Violation::DuplicateDelivery(1, 2, "a".to_string()),
+            Violation::NeverBroadcast(1, 1, "x".to_string()),
+            Violation::CommitGap(1, 2, 480, 100),
+        ]);
+    }
+}
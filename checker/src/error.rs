@@ -0,0 +1,18 @@
+use thiserror::Error;
+
+pub type CheckerResult<T> = Result<T, CheckerError>;
+
+#[derive(Error, Debug)]
+pub enum CheckerError {
+    #[error("Failed to access commit log: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Malformed record in {0} at line {1}: {2}")]
+    MalformedRecord(String, usize, serde_json::Error),
+
+    #[error("Unexpected record in {0} at line {1}, logs must start with a single Start record")]
+    UnexpectedRecord(String, usize),
+
+    #[error("Commit log {0} is empty")]
+    EmptyLog(String),
+}
@@ -0,0 +1,7 @@
+pub mod check;
+pub mod error;
+pub mod log;
+
+pub use crate::check::{check_prefix_consistency, Checker, Violation};
+pub use crate::error::{CheckerError, CheckerResult};
+pub use crate::log::{LogRecord, LogWriter, NodeLog, VertexRecord};
@@ -0,0 +1,100 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use model::committee::Id;
+use model::vertex::Vertex;
+use model::Round;
+
+use crate::error::{CheckerError, CheckerResult};
+
+/// A vertex as it is written in the commit log of a node.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VertexRecord {
+    pub round: Round,
+    /// Base64 encoded hash of the vertex, as printed in the logs of the node.
+    pub hash: String,
+    /// Base64 encoded key of the node that created the vertex.
+    pub owner: String,
+    /// Time at which the record was written, in ms since the Unix epoch.
+    pub time: u64,
+}
+
+impl VertexRecord {
+    pub fn new(vertex: &Vertex) -> Self {
+        Self {
+            round: vertex.round(),
+            hash: base64::encode(vertex.hash()),
+            owner: base64::encode(vertex.owner()),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+        }
+    }
+}
+
+/// One line of a commit log.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum LogRecord {
+    /// First record of every log, it identifies the node which wrote it.
+    Start(Id),
+    /// The node broadcast one of its own vertices.
+    Broadcast(VertexRecord),
+    /// The node delivered the vertex in this position of its total order.
+    Commit(VertexRecord),
+}
+
+/// Content of the commit log of a node.
+#[derive(Clone, Debug, Default)]
+pub struct NodeLog {
+    pub node_id: Id,
+    pub broadcast: Vec<VertexRecord>,
+    pub committed: Vec<VertexRecord>,
+}
+
+impl NodeLog {
+    /// Read a log written by `LogWriter`.
+    pub fn read<P: AsRef<Path>>(path: P) -> CheckerResult<Self> {
+        let path = path.as_ref();
+        let reader = BufReader::new(File::open(path)?);
+        let mut log: Option<NodeLog> = None;
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str(&line)
+                .map_err(|e| CheckerError::MalformedRecord(path.display().to_string(), index + 1, e))?;
+            match (record, log.as_mut()) {
+                (LogRecord::Start(node_id), None) => log = Some(NodeLog { node_id, ..NodeLog::default() }),
+                (LogRecord::Broadcast(vertex), Some(log)) => log.broadcast.push(vertex),
+                (LogRecord::Commit(vertex), Some(log)) => log.committed.push(vertex),
+                _ => return Err(CheckerError::UnexpectedRecord(path.display().to_string(), index + 1)),
+            }
+        }
+        log.ok_or_else(|| CheckerError::EmptyLog(path.display().to_string()))
+    }
+}
+
+/// Appends the records of a node to its commit log, one JSON object per line.
+pub struct LogWriter {
+    file: File,
+}
+
+impl LogWriter {
+    pub fn create<P: AsRef<Path>>(path: P, node_id: Id) -> CheckerResult<Self> {
+        let mut writer = Self { file: File::create(path)? };
+        writer.write(&LogRecord::Start(node_id))?;
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, record: &LogRecord) -> CheckerResult<()> {
+        let line = serde_json::to_string(record).expect("Failed to serialize log record");
+        writeln!(self.file, "{}", line)?;
+        Ok(())
+    }
+}
@@ -11,10 +11,11 @@ pub struct ABSE {
     info: Vec<u64>,  // A structure used to record voting information
     baseline: f64,  // the baseline score of leader election
     size: usize,  // The size for checking the queue length (confirm)
+    mean_stake: f64,  // the mean stake of the validators, the unit of the scores and the baseline
 }
 
 impl ABSE {
-    pub fn new(size: usize, f:u64) -> ABSE {
+    pub fn new(size: usize, f:u64, mean_stake: f64) -> ABSE {
         ABSE {
             r: 0,
             f,
@@ -24,6 +25,7 @@ impl ABSE {
             info: Vec::new(),  // TODO: Initialize with actual voting information
             baseline: 0.0,
             size,
+            mean_stake,
         }
     }
 
@@ -78,7 +80,8 @@ impl ABSE {
       self.scores_i.push_back(s);
       // A new baseline is obtained based on r. The computation rules can be specialized for different scenarios.
       //self.baseline = (self.r as f64 * (2.0 * f + 1.0)) / (3.0 * f + 1.0);
-      self.baseline = ((self.r as f64 - self.size as f64 - 1.0).max(0.0)) * (2 * self.f + 1) as f64 / (3 * self.f + 1) as f64 / 6.0;
+      // Scores add up the stake of the votes, so the baseline is the one of a validator of mean stake.
+      self.baseline = ((self.r as f64 - self.size as f64 - 1.0).max(0.0)) * (2 * self.f + 1) as f64 / (3 * self.f + 1) as f64 / 6.0 * self.mean_stake;
       // On a 4 rounds (a wave) basis.
       Ok(())
     }
@@ -104,14 +107,14 @@ mod tests {
 
     #[test]
     fn test_abse_new() {
-        let abse = ABSE::new(5,2);
+        let abse = ABSE::new(5,2,1.0);
         assert_eq!(abse.size, 5);
         assert_eq!(abse.scores_i.len(), 0);
     }
 
     #[test]
     fn test_abse_generate() {
-        let mut abse = ABSE::new(2,2);
+        let mut abse = ABSE::new(2,2,1.0);
         // abse.scores_i.push_back(vec![1, 2, 3]);
         // abse.scores_i.push_back(vec![2, 3, 4]);
         abse.set_info(vec![1, 2, 3]);
@@ -139,7 +142,7 @@ mod tests {
 
     #[test]
     fn test_abse_update() {
-        let mut abse = ABSE::new(2,2);
+        let mut abse = ABSE::new(2,2,1.0);
         abse.update_round(4);
         //abse.set_info(vec![1, 2, 3]);
         abse.set_info(Vec::new());
@@ -156,7 +159,7 @@ mod tests {
 
     #[test]
     fn test_abse_judge() {
-        let mut abse = ABSE::new(2,2);
+        let mut abse = ABSE::new(2,2,1.0);
         //abse.ref_s = vec![1.0, 2.0, 3.0];
         abse.update_round(1);
         //abse.set_info(Vec::new());
@@ -165,4 +168,19 @@ mod tests {
         assert_eq!(abse.judge(1), true);
         assert_eq!(abse.judge(2), true);
     }
+
+    #[test]
+    fn test_abse_judge_stakes() {
+        // Stakes 1 and 3, so the mean stake is 2.
+        let mut abse = ABSE::new(1,1,2.0);
+        abse.update_round(34);
+        // Both validators voted 4 times, each vote adding its stake.
+        abse.set_info(vec![4, 12]);
+        abse.update().unwrap();
+        abse.set_info(Vec::new());
+        abse.update().unwrap();
+        assert_eq!(abse.baseline, 8.0);
+        assert!(!abse.judge(0));
+        assert!(abse.judge(1));
+    }
 }
\ No newline at end of file
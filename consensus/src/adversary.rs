@@ -0,0 +1,363 @@
+use std::collections::BTreeMap;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use model::committee::{Committee, Id};
+use model::vertex::{Vertex, VertexHash};
+use model::Round;
+
+use crate::core::{Action, MAX_WAVE};
+
+/// Behaviour of a node when it creates and sends its own vertices. Correct nodes use `Honest`, the
+/// other implementations are Byzantine strategies used to evaluate the protocol under attack.
+pub trait Adversary: Send {
+    /// Choose the parents of the vertex created in `round`. `previous` holds the vertices of the
+    /// previous round, which a correct node references, and `stale` the vertices of the round before.
+    fn parents(
+        &mut self,
+        _round: Round,
+        previous: BTreeMap<VertexHash, Round>,
+        _stale: BTreeMap<VertexHash, Round>,
+    ) -> BTreeMap<VertexHash, Round> {
+        previous
+    }
+
+    /// Return the actions sending a vertex created by this node. A correct node broadcasts it right away.
+    fn send(&mut self, vertex: Vertex) -> Vec<Action> {
+        vec![Action::BroadcastVertex(vertex)]
+    }
+
+    /// Whether the node drops every packet it receives while in `round`.
+    fn drops_packets(&self, _round: Round) -> bool {
+        false
+    }
+}
+
+/// Parse a strategy given on the command line, e.g. `withhold:2,3` or `delay_leader:4`.
+/// Strategies:
+///  - `honest`: behave correctly.
+///  - `drop`: drop every received packet (`--pretend_failure=1`).
+///  - `silent`: never send vertices, as if crashed, while still following the DAG.
+///  - `equivocate`: send one vertex to half of the committee and a different one to the other half.
+///  - `withhold:<ids>`: never send vertices to the given nodes.
+///  - `stale_parents`: reference the vertices of two rounds ago instead of the previous round.
+///  - `delay_leader:<rounds>`: hold the vertices of the first round of each wave for some rounds.
+///  - `malformed`: send bytes which do not decode as a vertex to the other nodes.
+///  - `wave_withholding`: hold all vertices until the end of the wave (`--pretend_failure=2`).
+pub fn parse_adversary(strategy: &str, node_id: Id, committee: &Committee) -> Option<Box<dyn Adversary>> {
+    let (name, argument) = match strategy.split_once(':') {
+        Some((name, argument)) => (name, Some(argument)),
+        None => (strategy, None),
+    };
+    let mut others: Vec<Id> = committee.validators.keys().copied().filter(|id| *id != node_id).collect();
+    others.sort_unstable();
+
+    let adversary: Box<dyn Adversary> = match (name, argument) {
+        ("honest", None) => Box::new(Honest),
+        ("drop", None) => Box::new(DropPackets),
+        ("silent", None) => Box::new(Silent),
+        ("equivocate", None) => {
+            // This node receives the first version of its vertices, so that it keeps going.
+            let (first, second) = others.split_at(others.len() / 2);
+            Box::new(Equivocation {
+                first_half: [vec![node_id], first.to_vec()].concat(),
+                second_half: second.to_vec(),
+            })
+        }
+        ("withhold", Some(ids)) => {
+            let victims = ids.split(',').map(|id| id.trim().parse::<Id>()).collect::<Result<Vec<_>, _>>().ok()?;
+            let recipients = [vec![node_id], others]
+                .concat()
+                .into_iter()
+                .filter(|id| !victims.contains(id))
+                .collect();
+            Box::new(Withholding { recipients })
+        }
+        ("stale_parents", None) => Box::new(StaleParents),
+        ("delay_leader", Some(rounds)) => Box::new(DelayLeaderRound {
+            delay: rounds.parse().ok()?,
+            held: vec![],
+        }),
+        ("malformed", None) => Box::new(Malformed { node_id, others }),
+        ("wave_withholding", None) => Box::new(WaveWithholding { held: vec![] }),
+        _ => return None,
+    };
+    Some(adversary)
+}
+
+pub struct Honest;
+
+impl Adversary for Honest {}
+
+/// Drops everything it receives, so it never moves past its current round.
+pub struct DropPackets;
+
+impl Adversary for DropPackets {
+    fn drops_packets(&self, _round: Round) -> bool {
+        true
+    }
+}
+
+/// Sends conflicting vertices for the same round to two halves of the committee.
+pub struct Equivocation {
+    first_half: Vec<Id>,
+    second_half: Vec<Id>,
+}
+
+impl Adversary for Equivocation {
+    fn send(&mut self, vertex: Vertex) -> Vec<Action> {
+        let twin = Vertex::new(vertex.owner(), vertex.round(), vec![], vertex.get_all_parents());
+        info!("Equivocate in round {}: {} and {}", vertex.round(), vertex, twin);
+        vec![
+            Action::SendVertex(vertex, self.first_half.clone()),
+            Action::SendVertex(twin, self.second_half.clone()),
+        ]
+    }
+}
+
+/// Never sends its vertices to some nodes of the committee.
+pub struct Withholding {
+    recipients: Vec<Id>,
+}
+
+impl Adversary for Withholding {
+    fn send(&mut self, vertex: Vertex) -> Vec<Action> {
+        vec![Action::SendVertex(vertex, self.recipients.clone())]
+    }
+}
+
+/// Keeps its vertices for itself, so the other nodes see it as crashed.
+pub struct Silent;
+
+impl Adversary for Silent {
+    fn send(&mut self, _vertex: Vertex) -> Vec<Action> {
+        vec![]
+    }
+}
+
+/// References only the vertices of two rounds ago, so that its vertices never strongly link to
+/// the vertices of the previous round.
+pub struct StaleParents;
+
+impl Adversary for StaleParents {
+    fn parents(
+        &mut self,
+        _round: Round,
+        previous: BTreeMap<VertexHash, Round>,
+        stale: BTreeMap<VertexHash, Round>,
+    ) -> BTreeMap<VertexHash, Round> {
+        if stale.is_empty() {
+            previous
+        } else {
+            stale
+        }
+    }
+}
+
+/// Holds the vertices of the first round of each wave (the round of the wave leader) for `delay`
+/// rounds before sending them.
+pub struct DelayLeaderRound {
+    delay: Round,
+    held: Vec<Vertex>,
+}
+
+impl Adversary for DelayLeaderRound {
+    fn send(&mut self, vertex: Vertex) -> Vec<Action> {
+        let round = vertex.round();
+        let (released, held) = self.held.drain(..).partition(|v| v.round() + self.delay <= round);
+        self.held = held;
+
+        let mut actions: Vec<Action> = released.into_iter().map(Action::BroadcastVertex).collect();
+        if round % MAX_WAVE == 1 && self.delay > 0 {
+            info!("Hold the vertex {} of the leader round", vertex);
+            self.held.push(vertex);
+        } else {
+            actions.push(Action::BroadcastVertex(vertex));
+        }
+        actions
+    }
+}
+
+/// Sends garbage to the other nodes in place of its vertices.
+pub struct Malformed {
+    node_id: Id,
+    others: Vec<Id>,
+}
+
+impl Adversary for Malformed {
+    fn send(&mut self, vertex: Vertex) -> Vec<Action> {
+        // The hash alone is too short to be decoded as a vertex.
+        let garbage = vertex.hash().to_vec();
+        vec![
+            Action::SendVertex(vertex, vec![self.node_id]),
+            Action::SendMalformed(garbage, self.others.clone()),
+        ]
+    }
+}
+
+/// Holds all its vertices until the last round of the wave, each of them also referencing the
+/// previous held one, and then sends them in reverse order (the adversary described in the paper).
+pub struct WaveWithholding {
+    held: Vec<Vertex>,
+}
+
+impl Adversary for WaveWithholding {
+    fn parents(
+        &mut self,
+        _round: Round,
+        mut previous: BTreeMap<VertexHash, Round>,
+        _stale: BTreeMap<VertexHash, Round>,
+    ) -> BTreeMap<VertexHash, Round> {
+        if let Some(last) = self.held.last() {
+            previous.insert(last.hash(), last.round());
+        }
+        previous
+    }
+
+    fn send(&mut self, vertex: Vertex) -> Vec<Action> {
+        let round = vertex.round();
+        self.held.push(vertex);
+        if round.is_multiple_of(MAX_WAVE) {
+            self.held.drain(..).rev().map(Action::BroadcastVertex).collect()
+        } else {
+            vec![]
+        }
+    }
+}
+
+/// A strategy followed by a node during a range of rounds, as read from a fault schedule.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FaultPeriod {
+    /// First round of the period.
+    pub from_round: Round,
+    /// Round at which the node recovers, if it does, after the first round.
+    #[serde(default)]
+    pub until_round: Option<Round>,
+    /// Repeat the period every given number of rounds, to alternate between faulty and correct. Not
+    /// zero.
+    #[serde(default)]
+    pub every: Option<Round>,
+    /// Strategy of the node during the period, with the syntax of `parse_adversary`.
+    pub strategy: String,
+}
+
+impl FaultPeriod {
+    pub fn contains(&self, round: Round) -> bool {
+        if round < self.from_round {
+            return false;
+        }
+        match (self.until_round, self.every) {
+            (Some(until_round), Some(every)) if every > 0 => {
+                (round - self.from_round) % every < until_round.saturating_sub(self.from_round)
+            }
+            (Some(until_round), _) => round < until_round,
+            (None, _) => true,
+        }
+    }
+}
+
+/// Build an adversary following the strategy of the first period containing the current round, and
+/// `fallback` outside of all periods. The round is the highest one the node saw, its own or one of
+/// the vertices of the others: a node dropping its packets does not move to the next rounds, but
+/// still sees the rounds of the vertices it drops, so it recovers once the others reach the end of
+/// its period. Vertices held by a strategy when its period ends are never sent.
+pub fn parse_schedule(
+    periods: &[FaultPeriod],
+    fallback: Box<dyn Adversary>,
+    node_id: Id,
+    committee: &Committee,
+) -> Result<Box<dyn Adversary>, String> {
+    let periods = periods
+        .iter()
+        .map(|period| {
+            if period.until_round.is_some_and(|until_round| until_round <= period.from_round) {
+                return Err(format!("the period from round {} ends before it starts", period.from_round));
+            }
+            if period.every == Some(0) {
+                return Err(format!("the period from round {} repeats every 0 rounds", period.from_round));
+            }
+            let adversary = parse_adversary(&period.strategy, node_id, committee)
+                .ok_or_else(|| format!("unknown adversary strategy {}", period.strategy))?;
+            Ok((period.clone(), adversary))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Box::new(Scheduled { periods, fallback }))
+}
+
+/// Switches between strategies depending on the round, following a fault schedule.
+pub struct Scheduled {
+    periods: Vec<(FaultPeriod, Box<dyn Adversary>)>,
+    fallback: Box<dyn Adversary>,
+}
+
+impl Scheduled {
+    fn at(&mut self, round: Round) -> &mut Box<dyn Adversary> {
+        match self.periods.iter_mut().find(|(period, _)| period.contains(round)) {
+            Some((_, adversary)) => adversary,
+            None => &mut self.fallback,
+        }
+    }
+}
+
+impl Adversary for Scheduled {
+    fn parents(
+        &mut self,
+        round: Round,
+        previous: BTreeMap<VertexHash, Round>,
+        stale: BTreeMap<VertexHash, Round>,
+    ) -> BTreeMap<VertexHash, Round> {
+        self.at(round).parents(round, previous, stale)
+    }
+
+    fn send(&mut self, vertex: Vertex) -> Vec<Action> {
+        self.at(vertex.round()).send(vertex)
+    }
+
+    fn drops_packets(&self, round: Round) -> bool {
+        match self.periods.iter().find(|(period, _)| period.contains(round)) {
+            Some((_, adversary)) => adversary.drops_packets(round),
+            None => self.fallback.drops_packets(round),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_adversary() {
+        let committee = Committee::generate_from_seed(4, 0);
+        for strategy in ["honest", "drop", "silent", "equivocate", "withhold:1, 2", "stale_parents", "delay_leader:2", "malformed", "wave_withholding"] {
+            assert!(parse_adversary(strategy, 4, &committee).is_some(), "{}", strategy);
+        }
+        for strategy in ["", "crash", "withhold", "withhold:a", "delay_leader:-1", "honest:1"] {
+            assert!(parse_adversary(strategy, 4, &committee).is_none(), "{}", strategy);
+        }
+    }
+
+    #[test]
+    fn test_alternating_fault_period() {
+        let period = FaultPeriod { from_round: 10, until_round: Some(15), every: Some(20), strategy: "drop".to_string() };
+        let faulty: Vec<Round> = (1..=50).filter(|round| period.contains(*round)).collect();
+        assert_eq!(faulty, [10, 11, 12, 13, 14, 30, 31, 32, 33, 34, 50]);
+
+        let committee = Committee::generate_from_seed(4, 0);
+        let adversary = parse_schedule(&[period], Box::new(Honest), 4, &committee).unwrap();
+        assert!(!adversary.drops_packets(9));
+        assert!(adversary.drops_packets(12));
+        assert!(!adversary.drops_packets(15));
+    }
+
+    #[test]
+    fn test_invalid_fault_period() {
+        let committee = Committee::generate_from_seed(4, 0);
+        let ends_before = FaultPeriod { from_round: 10, until_round: Some(5), every: Some(20), strategy: "drop".to_string() };
+        assert!(parse_schedule(&[ends_before], Box::new(Honest), 4, &committee).is_err());
+        let never_repeats = FaultPeriod { from_round: 10, until_round: Some(15), every: Some(0), strategy: "drop".to_string() };
+        assert!(parse_schedule(&[never_repeats], Box::new(Honest), 4, &committee).is_err());
+        let unknown = FaultPeriod { from_round: 10, until_round: None, every: None, strategy: "crash".to_string() };
+        assert!(parse_schedule(&[unknown], Box::new(Honest), 4, &committee).is_err());
+    }
+}
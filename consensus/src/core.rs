@@ -0,0 +1,785 @@
+use std::collections::{HashMap, HashSet};
+use log::{debug, info, warn};
+
+use model::{Round, Wave};
+use model::block::{Block, BlockHash};
+use model::committee::{Committee, Id, NodePublicKey};
+use model::epoch::{Epochs, Reconfiguration};
+use model::transaction::CommitPosition;
+use model::vertex::Vertex;
+
+use crate::adversary::Adversary;
+use crate::proposals::{ProposalBudget, ProposalQueue};
+use crate::state::State;
+use crate::abse::ABSE;
+
+/// Number of rounds of a wave. Wave `w` is made of rounds `4 * (w - 1) + 1` to `4 * w`.
+pub const MAX_WAVE: Wave = 4;
+
+/// Number of waves between the wave of the leader committing a reconfiguration and the first wave
+/// of the new epoch. Every node commits the same leaders, so they all switch at the same round,
+/// provided they commit the reconfiguration before reaching it.
+pub const EPOCH_CHANGE_DELAY: Wave = 4;
+
+/// Inputs of the consensus state machine.
+#[derive(Debug)]
+pub enum Event {
+    /// A vertex broadcast by one of the nodes (including this one) has been received.
+    VertexReceived(Vertex),
+    /// A block of this node is available to be proposed in one of its next vertices, unless it was
+    /// requested for the vertex of another node.
+    BlockAvailable(Block),
+    /// A block of another node, which this node stores without proposing it.
+    BlockReceived(Block),
+    /// A block this node requested for a vertex of another node, sent back by the owner of the vertex.
+    BlockReplied(Block),
+    /// A worker of this node stores a batch, which this node proposes if the worker sealed it.
+    BatchAvailable(BlockHash, bool),
+    /// A node asks for the block of one of the vertices of this node.
+    BlockRequested(BlockHash, Id),
+    /// A timer of the driver fired while blocks are missing, which this node requests again.
+    TimerFired,
+}
+
+/// Outputs of the consensus state machine, to be carried out by the driver.
+#[derive(Debug)]
+pub enum Action {
+    /// Send the vertex to every node of the committee (including this one).
+    BroadcastVertex(Vertex),
+    /// Send the vertex only to the given nodes.
+    SendVertex(Vertex, Vec<Id>),
+    /// Send bytes which are not a valid vertex to the given nodes.
+    SendMalformed(Vec<u8>, Vec<Id>),
+    /// Ask the given node for the block of one of its vertices, missing from this node.
+    RequestBlock(BlockHash, Id),
+    /// Send a block to the node which requested it.
+    SendBlock(Block, Id),
+    /// The vertex has been committed and is delivered with the payload of each of its blocks in this
+    /// position of the total order.
+    OutputCommit(Vertex, Vec<Payload>, CommitPosition),
+}
+
+/// What a committed vertex delivers. A block or batch is only delivered with the first committed
+/// vertex carrying it, even if several nodes proposed it.
+#[derive(Debug)]
+pub enum Payload {
+    Block(Block),
+    /// A batch stored by the workers, only known by its digest.
+    Batch(BlockHash),
+    /// The block or batch was already delivered with an earlier vertex.
+    Duplicate(BlockHash),
+}
+
+/// DAG-Rider with ABSE leader election as a synchronous state machine: it does no I/O and only
+/// turns `Event`s into `Action`s, leaving the network and the runtime to the driver (see `Consensus`).
+pub struct ConsensusCore {
+    node_id: Id,
+    epochs: Epochs,
+    decided_wave: Wave,
+    state: State,
+    buffer: Vec<Vertex>,
+    proposals: ProposalQueue,
+    /// Blocks queued to be proposed by this node or carried by a vertex of the DAG, which are not
+    /// proposed again.
+    proposed_blocks: HashSet<BlockHash>,
+    /// Blocks of buffered vertices requested from their owner and not received yet.
+    requested_blocks: HashSet<BlockHash>,
+    /// Number of vertices committed so far.
+    committed: u64,
+    /// Highest round of the vertices of the others, which a node dropping its packets still sees
+    /// to know when its fault schedule has it recover.
+    observed_round: Round,
+    abse_struct:ABSE,
+    score_array: Vec<u64>,
+    id_to_index: HashMap<NodePublicKey, usize>,
+    adversary: Box<dyn Adversary>,
+}
+
+impl ConsensusCore {
+    pub fn new(node_id: Id, committee: Committee, adversary: Box<dyn Adversary>) -> Self {
+        let state = State::new(Vertex::genesis(committee.get_nodes_keys()));
+        let (abse_struct, score_array, id_to_index) = Self::new_abse(&committee);
+        Self {
+            node_id,
+            epochs: Epochs::new(committee),
+            decided_wave: 0,
+            state,
+            buffer: vec![],
+            proposals: ProposalQueue::new(ProposalBudget::default()),
+            proposed_blocks: HashSet::new(),
+            requested_blocks: HashSet::new(),
+            committed: 0,
+            observed_round: 0,
+            abse_struct,
+            score_array,
+            id_to_index,
+            adversary,
+        }
+    }
+
+    /// Limit the blocks proposed by each vertex of this node.
+    pub fn with_budget(mut self, budget: ProposalBudget) -> Self {
+        self.proposals.set_budget(budget);
+        self
+    }
+
+    /// ABSE scores start over with each committee.
+    fn new_abse(committee: &Committee) -> (ABSE, Vec<u64>, HashMap<NodePublicKey, usize>) {
+        let csize = committee.size().clone();
+        let score_array = vec![0; csize];
+        // The maximum stake of the faulty validators.
+        let faulties = committee.total_stake() - committee.quorum_threshold();
+        let mut id_to_index = HashMap::new();
+        for (_, validator) in committee.validators.iter() {
+          let public_key = &validator.public_key;
+          id_to_index.insert(public_key.clone(), id_to_index.len());
+        }
+        let mean_stake = committee.total_stake() as f64 / csize as f64;
+        (ABSE::new(3, faulties, mean_stake), score_array, id_to_index)
+    }
+
+    /// Process an event and return the actions it triggers, in the order they must be carried out.
+    pub fn handle(&mut self, event: Event) -> Vec<Action> {
+        let mut actions = vec![];
+        match event {
+            Event::VertexReceived(vertex) => self.add_vertex(vertex, &mut actions),
+            Event::BlockAvailable(block) => self.add_block(block, true),
+            Event::BlockReceived(block) => self.add_block(block, false),
+            Event::BlockReplied(block) => match self.requested_blocks.contains(&block.hash()) {
+                true => self.add_block(block, false),
+                // The block may have arrived from its builder in the meantime.
+                false => debug!("Drop a block reply this node did not request"),
+            },
+            Event::BatchAvailable(block_hash, sealed) => self.add_batch(block_hash, sealed),
+            Event::BlockRequested(block_hash, id) => match self.state.blocks.get(&block_hash) {
+                Some(block) => actions.push(Action::SendBlock(block.clone(), id)),
+                None => debug!("Node {} requested a block this node does not have", id),
+            },
+            Event::TimerFired => self.request_missing_blocks(&mut actions),
+        }
+
+        debug!("Consensus goes to the next iteration");
+        //debug!("block to propose:{}",self.blocks_to_propose.is_empty());
+        self.try_to_advance_round(&mut actions);
+        actions
+    }
+
+    /// Buffer a vertex received from the network, asking its owner for the blocks it is missing, and
+    /// add the buffered vertices to the DAG.
+    fn add_vertex(&mut self, vertex: Vertex, actions: &mut Vec<Action>) {
+        debug!("Vertex received in consensus of 'node {}': {}", self.node_id, vertex);
+        // The rounds come from the peer, and the DAG counts on a vertex following its parents.
+        if vertex.round() == 0 || vertex.parents().values().any(|round| *round >= vertex.round()) {
+            warn!("Drop the vertex {} whose round does not follow the rounds of its parents", vertex);
+            return;
+        }
+        if self.drops_packets() {
+            self.observed_round = self.observed_round.max(vertex.round());
+            return;
+        }
+        let committee = self.epochs.committee_at(vertex.round());
+        let owner = match committee.get_node_id(&vertex.owner()) {
+            Some(owner) => owner,
+            None => {
+                warn!("Drop the vertex {} whose owner is not in the committee of its round", vertex);
+                return;
+            }
+        };
+        for block_hash in vertex.block_hashes() {
+            if !self.state.has_block(block_hash) && self.requested_blocks.insert(*block_hash) {
+                debug!("Request a block of the vertex {} from node {}", vertex, owner);
+                actions.push(Action::RequestBlock(*block_hash, owner));
+            }
+        }
+        self.buffer.push(vertex);
+        self.insert_buffered_vertices();
+    }
+
+    /// Whether buffered vertices wait for blocks requested from their owner.
+    pub fn misses_blocks(&self) -> bool {
+        !self.requested_blocks.is_empty()
+    }
+
+    /// Ask the owners of the buffered vertices again for the blocks still missing, as the request or
+    /// the reply may have been lost.
+    fn request_missing_blocks(&mut self, actions: &mut Vec<Action>) {
+        if self.drops_packets() {
+            return;
+        }
+        let mut requested = HashSet::new();
+        for vertex in &self.buffer {
+            let owner = match self.epochs.committee_at(vertex.round()).get_node_id(&vertex.owner()) {
+                Some(owner) => owner,
+                None => continue,
+            };
+            for block_hash in vertex.block_hashes() {
+                if self.requested_blocks.contains(block_hash) && requested.insert(*block_hash) {
+                    debug!("Request a block of the vertex {} from node {} again", vertex, owner);
+                    actions.push(Action::RequestBlock(*block_hash, owner));
+                }
+            }
+        }
+    }
+
+    /// Add to the DAG every buffered vertex whose parents and blocks are already known.
+    fn insert_buffered_vertices(&mut self) {
+        // Go through buffer and add vertex in the dag which meets the requirements
+        // and remove from the buffer those added
+        self.buffer.retain(|v| {
+            if v.round() <= self.state.current_round
+                && self.state.dag.contains_vertices(v.parents())
+                && v.block_hashes().iter().all(|block_hash| self.state.has_block(block_hash))
+            {
+            // if v.round() <= self.state.current_round {
+                // Commits rely on every vertex strongly linking to a quorum of the previous round, so
+                // vertices of Byzantine nodes which do not are dropped.
+                if v.round() > 1 {
+                    let committee = self.epochs.committee_at(v.round() - 1);
+                    if self.state.dag.get_stake(&v.get_strong_parents(), committee) < committee.quorum_threshold() {
+                        warn!("Drop the vertex {} which has too few strong parents", v);
+                        return false;
+                    }
+                }
+                // Another node may have proposed a block this node has queued.
+                for block_hash in v.block_hashes() {
+                    self.proposed_blocks.insert(*block_hash);
+                    self.proposals.remove(block_hash);
+                }
+                self.state.dag.insert_vertex(v.clone());
+                false
+            } else {
+                true
+            }
+        })
+    }
+
+    /// Store a block, and queue it to be proposed in one of the next vertices of this node unless it
+    /// was requested for the vertex of another node or is already proposed.
+    fn add_block(&mut self, block: Block, propose: bool) {
+        // Vertices only carry the digests, so a block forged under the digest of another would
+        // deliver other transactions on this node than on the others.
+        if !block.verify() {
+            warn!("Drop a block whose digest is not the one of its transactions");
+            return;
+        }
+        let block_hash = block.hash();
+        let size = block.transactions.iter().map(Vec::len).sum();
+        self.state.blocks.entry(block_hash).or_insert(block);
+        if self.requested_blocks.remove(&block_hash) {
+            self.insert_buffered_vertices();
+        } else if propose {
+            self.propose(block_hash, size);
+        }
+    }
+
+    /// Record a batch stored by a worker of this node, and queue it to be proposed if the worker
+    /// sealed it.
+    fn add_batch(&mut self, block_hash: BlockHash, sealed: bool) {
+        self.state.batches.insert(block_hash);
+        if self.requested_blocks.remove(&block_hash) {
+            self.insert_buffered_vertices();
+        }
+        if sealed {
+            self.propose(block_hash, 0);
+        }
+    }
+
+    fn propose(&mut self, block_hash: BlockHash, size: usize) {
+        if self.proposed_blocks.insert(block_hash) {
+            self.proposals.push(block_hash, size, self.state.current_round);
+        } else {
+            debug!("Do not propose a block which is already proposed");
+        }
+    }
+
+    /// Move to the next round if the DAG has reached the quorum for the current one. Committed
+    /// vertices and the new vertex to broadcast are pushed to `actions`.
+    fn try_to_advance_round(&mut self, actions: &mut Vec<Action>) {
+        let committee = self.epochs.committee_at(self.state.current_round);
+        if !self.proposals.is_empty() && self.state.dag.is_quorum_reached_for_round(&(self.state.current_round), committee) {
+            info!("DAG has reached the quorum for the round {:?}", self.state.current_round);
+            if Self::is_last_round_in_wave(self.state.current_round) {
+                info!("Finished the last round {:?} in the wave. Start to order vertices", self.state.current_round);
+                let wave = self.state.current_round / MAX_WAVE;
+                let ordered_vertices = self.get_ordered_vertices(wave);
+
+                info!("Got {} vertices to order", ordered_vertices.len());
+                for vertex in ordered_vertices {
+                    let payload = self.payload(&vertex);
+                    let position = CommitPosition { wave, sequence: self.committed };
+                    self.committed += 1;
+                    actions.push(Action::OutputCommit(vertex, payload, position));
+                }
+            }
+            // when quorum for the round reached, then go to the next round
+
+            self.state.current_round += 1;
+            let current_round = self.state.current_round.clone();
+            self.epochs.advance_to(current_round);
+            if self.epochs.epoch_at(current_round) != self.epochs.epoch_at(current_round - 1) {
+                info!("Start epoch {} in round {}", self.epochs.epoch_at(current_round), current_round);
+                (self.abse_struct, self.score_array, self.id_to_index) = Self::new_abse(self.epochs.current());
+            }
+            debug!("DAG goes to the next round {:?},
+            the DAG graph shown below contains both strong and weak edges 
+             \n{}", self.state.current_round, self.state.dag);
+            
+            // The ABSE baseline grows with the rounds, which start over with the scores in each epoch.
+            let abse_round = current_round - self.epochs.first_round_at(current_round);
+            if self.abse_struct.get_r() < abse_round {
+              let s_array = self.get_array().to_vec();
+              debug!("Success! Current array is: {:?}", s_array);
+              self.abse_struct.set_info(s_array);
+              self.reset_array();
+              self.abse_struct.update_round(abse_round);
+              self.abse_struct.update();
+              self.abse_struct.set_info(Vec::new());
+              debug!("{:?}: ABSE Struct", self.abse_struct);
+            }
+            
+            if !self.epochs.current().validators.contains_key(&self.node_id) {
+                // Nodes out of the committee keep following the DAG without proposing.
+                debug!("Node {} is not in the committee of round {}", self.node_id, current_round);
+                return;
+            }
+            let new_vertex = self.create_new_vertex(self.state.current_round).unwrap();
+
+            info!("Broadcast the new vertex {}", new_vertex);
+            actions.extend(self.adversary.send(new_vertex));
+            // let new_vertex = self.create_new_vertex(self.state.current_round).unwrap();
+
+            // info!("Broadcast the new vertex {}", new_vertex);
+            // actions.push(Action::BroadcastVertex(new_vertex));
+            //debug!("Broadcast the new vertex successfully!");
+        }
+    }
+
+    /// Payload delivered with a committed vertex, which is in the DAG so its blocks are available.
+    fn payload(&mut self, vertex: &Vertex) -> Vec<Payload> {
+        vertex
+            .block_hashes()
+            .iter()
+            .map(|block_hash| {
+                if !self.state.delivered_blocks.insert(*block_hash) {
+                    return Payload::Duplicate(*block_hash);
+                }
+                match self.state.blocks.get(block_hash) {
+                    Some(block) => Payload::Block(block.clone()),
+                    None => Payload::Batch(*block_hash),
+                }
+            })
+            .collect()
+    }
+
+    pub fn current_round(&self) -> Round {
+        self.state.current_round
+    }
+
+    /// Committees of the epochs scheduled so far.
+    pub fn epochs(&self) -> &Epochs {
+        &self.epochs
+    }
+
+    /// Whether the node should drop the packets it receives, in the highest round it saw.
+    pub fn drops_packets(&self) -> bool {
+        self.adversary.drops_packets(self.state.current_round.max(self.observed_round))
+    }
+
+    fn create_new_vertex(&mut self, round: Round) -> Option<Vertex> {
+        let block_hashes = self.proposals.take(round);
+        let transactions: usize = block_hashes
+            .iter()
+            .filter_map(|block_hash| self.state.blocks.get(block_hash))
+            .map(|block| block.transactions.len())
+            .sum();
+        info!(
+            "Start to create a new vertex with {} blocks and {} transactions, {} blocks left to propose",
+            block_hashes.len(),
+            transactions,
+            self.proposals.len()
+        );
+        let parents = self.adversary.parents(
+            round,
+            self.state.dag.get_vertices(&(round - 1)),
+            self.state.dag.get_vertices(&(round - 2)),
+        );
+        let mut vertex = Vertex::new(
+            self.epochs.committee_at(round).get_node_key(self.node_id).unwrap(),
+            round,
+            block_hashes,
+            parents,
+        );
+
+        if round > 2 {
+            self.set_weak_edges(&mut vertex, round);
+        }
+
+        return Some(vertex);
+    }
+
+    fn set_weak_edges(&self, vertex: &mut Vertex, round: Round) {
+        for r in (1..round - 2).rev() {
+            if let Some(vertices) = self.state.dag.graph.get(&r) {
+                for (_, v) in vertices {
+                    if !self.state.dag.is_linked(&vertex, v) {
+                        vertex.add_parent(v.hash(), r)
+                    }
+                }
+            }
+        }
+    }
+
+    fn get_ordered_vertices(&mut self, wave: Wave) -> Vec<Vertex> {
+        if let Some(leader) = self.get_wave_vertex_leader(wave) {
+            let wleader = leader.clone();
+            debug!("Selected a vertex leader: {}", leader);
+            // we need to make sure that if one correct process commits the wave
+            // vertex leader 𝑣, then all the other correct processes will commit 𝑣
+            // later. To this end, we use standard quorum intersection. Process 𝑝𝑖
+            // commits the wave 𝑤 vertex leader 𝑣 if:
+            let round = self.get_round_for_wave(wave, MAX_WAVE);
+            if self.state.dag.is_linked_with_others_in_round(leader, round, self.epochs.committee_at(round)) {
+                let linked_public_keys = self.state.dag.get_valid_vertices_voters(leader, round);
+                debug!("The leader is strongly linked to others in the round {}", round);
+                let mut leaders_to_commit = self.get_leaders_to_commit(wave - 1, leader);
+                self.decided_wave = wave;
+                debug!("Set decided wave to {}", wave);
+                for pubkey in linked_public_keys {
+                  self.set_voter_id(pubkey);
+                }
+                self.set_voter_id(wleader.owner());
+                // go through the un-committed leaders starting from the oldest one
+                return self.order_vertices(&mut leaders_to_commit);
+            }
+        }
+        return vec![];
+    }
+
+    fn get_leaders_to_commit(&self, from_wave: Wave, current_leader: &Vertex) -> Vec<Vertex> {
+        let mut to_commit = vec![current_leader.clone()];
+        let mut current_leader = current_leader;
+
+        if from_wave > 0 {
+            // Go for each wave up until decided_wave and find which leaders we need to commit
+            for wave in (self.decided_wave + 1..=from_wave).rev()
+            {
+                // Get the vertex proposed in the previous wave.
+                debug!("Get the vertex proposed in the previous wave.");
+                if let Some(prev_leader) = self.get_wave_vertex_leader(wave) {
+                    // if no strong link between leaders then skip for this wave
+                    // and maybe next time there will be a strong link
+                    if self.state.dag.is_strongly_linked(current_leader, prev_leader) {
+                        to_commit.push(prev_leader.clone());
+                        current_leader = prev_leader;
+                    }
+                }
+            }
+        }
+        to_commit
+    }
+
+    fn order_vertices(&mut self, leaders: &mut Vec<Vertex>) -> Vec<Vertex> {
+        let mut ordered_vertices = Vec::new();
+
+        // go from the oldest leader to the newest by taking items from the tail
+        while let Some(leader) = leaders.pop() {
+            debug!("Start ordering vertices from the leader: {:?}", leader);
+            let first_ordered = ordered_vertices.len();
+
+            for (round, vertices) in &self.state.dag.graph {
+                if *round > 0 {
+                    // Deliver the vertices of a round in the same order on every node.
+                    let mut vertices: Vec<&Vertex> = vertices.values().collect();
+                    vertices.sort_by_key(|v| v.owner());
+                    for vertex in vertices {
+                        let vertex_hash = vertex.hash();
+                        if !self.state.delivered_vertices.contains(&vertex_hash) && (vertex_hash == leader.hash() || self.state.dag.is_linked(&leader, vertex)) {
+                            ordered_vertices.push(vertex.clone());
+                            self.state.delivered_vertices.insert(vertex_hash);
+                        }
+                    }
+                }
+            }
+            let leader_wave = (leader.round() - 1) / MAX_WAVE + 1;
+            self.schedule_reconfigurations(leader_wave, &ordered_vertices[first_ordered..]);
+        }
+
+        ordered_vertices
+    }
+
+    /// Schedule the epoch started by the first reconfiguration found in the vertices ordered by the
+    /// leader of `leader_wave` and signed by a quorum of the last scheduled committee, if any.
+    fn schedule_reconfigurations(&mut self, leader_wave: Wave, vertices: &[Vertex]) {
+        let next_epoch = self.epochs.last_epoch() + 1;
+        let last_committee = self.epochs.last_committee();
+        let reconfiguration = vertices
+            .iter()
+            .flat_map(|vertex| vertex.block_hashes())
+            .filter_map(|block_hash| self.state.blocks.get(block_hash))
+            .flat_map(|block| &block.transactions)
+            .filter_map(|transaction| Reconfiguration::from_transaction(transaction))
+            .filter(|reconfiguration| reconfiguration.epoch == next_epoch)
+            .find(|reconfiguration| {
+                let authorized = reconfiguration.is_authorized(last_committee);
+                if !authorized {
+                    warn!("Ignore the committee of epoch {} without the signatures of a quorum of epoch {}", next_epoch, next_epoch - 1);
+                }
+                authorized
+            });
+        if let Some(reconfiguration) = reconfiguration {
+            let first_round = self.get_round_for_wave(leader_wave + EPOCH_CHANGE_DELAY, 1);
+            info!("Epoch {} of {} nodes starts in round {}", next_epoch, reconfiguration.committee.size(), first_round);
+            if first_round <= self.state.current_round {
+                warn!("Node {} committed the reconfiguration after round {}, it cannot switch in time", self.node_id, first_round);
+            }
+            self.epochs.schedule(first_round, reconfiguration.epoch, reconfiguration.committee);
+        }
+    }
+
+    fn get_wave_vertex_leader(&self, wave: Wave) -> Option<&Vertex> {
+        let first_round_of_wave = self.get_round_for_wave(wave, 1);
+        // let coin = first_round_of_wave;
+         let coin = wave;
+
+        // Elect the leader.
+        let leader = self.epochs.committee_at(first_round_of_wave).leader(coin);
+        let abse_s = self.abse_struct.clone();
+        if let Some(index) = self.get_index(leader.clone()){
+          if abse_s.judge(index) {
+            if self.choose_leader(){
+              debug!("{}-{:?}: can be the leader of wave {}", index, leader, wave);
+              self.state.dag.graph.get(&first_round_of_wave).map(|x| x.get(&leader)).flatten()
+            }else{
+              None
+            }
+          }else {
+            debug!("{}-{:?}: can not be the leader of wave {}, skip.", index, leader, wave);
+            None
+          }
+        }else{
+          None
+        }
+        //self.state.dag.graph.get(&first_round_of_wave).map(|x| x.get(&leader)).flatten()
+    }
+
+    fn choose_leader(&self) -> bool {
+    // //  Since the existing DAG protocols only emulate the 
+    // // building block of global perfect coin, we also choose to emulate 'choose_leader', 
+    // // but of course, we can also choose to use the following annotated form of broadcasting 
+    // // to realize it 
+    //
+    //   self.leader_choose_broadcaster.send(LeaderMessage::::new(
+    //     self.committee.get_node_key(self.node_id).unwrap(),
+    //     wave,
+    //   ));
+    //   let wave_map = self.leadermessages.entry(wave).or_default();
+    //   if wave_map.len() >= self.committee.quorum_threshold() {
+    //     true
+    //   }else{
+    //     false
+    //   }
+    //   // An additional thread would be needed to always perform 
+    //   // the function similar to the code below, 
+    //   // specifically by defining a leader_choose_coordinator like structure 
+    //   // and then spawn_run in main:
+    //
+    //   tokio::select! {
+    //     Some(lm) = self.leader_choose_receiver.recv() => {
+    //         let voter_id = lm.public_key;
+    //         // The type of leadermessages is similar to 
+    //         // pub leadermessages: HashMap<Wave, Vec<PublicKey>>,
+    //         let wave_map = self.leadermessages.entry(wave).or_default();
+    //         // TODO: check if voter_id is already in voters
+    //         wave_map.push(voter_id);
+    //     },
+    // }
+      true
+    }
+
+    fn get_round_for_wave(&self, wave: Wave, round: Round) -> Round {
+        (MAX_WAVE * (wave - 1) + round) as Round
+    }
+
+    fn is_last_round_in_wave(round: Round) -> bool {
+        round % MAX_WAVE == 0
+    }
+
+    fn get_index(&self, voter_id: NodePublicKey) -> Option<usize> {
+      self.id_to_index.get(&voter_id).cloned()
+    }
+
+    fn reset_array(&mut self) {
+      for item in &mut self.score_array {
+          *item = 0;
+      }
+    }
+  
+    fn get_array(&self) -> &[u64] {
+      &self.score_array
+    }
+
+    fn set_voter_id(&mut self, voter_id: NodePublicKey) {
+      //debug!("Set for: {:?}!",voter_id);
+      if let Some(index) = self.get_index(voter_id) {
+          self.score_array[index] += self.epochs.current().stake_of(&voter_id);
+          //debug!("Success! Current array is: {:?}", self.score_array);
+      }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    use crate::adversary::{parse_schedule, FaultPeriod, Honest};
+
+    #[test]
+    fn test_block_triggers_vertex_of_next_round() {
+        let committee = Committee::generate_from_seed(4, 0);
+        let mut core = ConsensusCore::new(1, committee.clone(), Box::new(Honest));
+
+        // The genesis round already has a quorum, the node only waits for a block to propose.
+        assert!(core.handle(Event::TimerFired).is_empty());
+        let actions = core.handle(Event::BlockAvailable(Block::new(vec![vec![1]])));
+
+        assert_eq!(actions.len(), 1);
+        match &actions[0] {
+            Action::BroadcastVertex(vertex) => {
+                assert_eq!(vertex.round(), 2);
+                assert_eq!(vertex.owner(), committee.get_node_key(1).unwrap());
+                assert_eq!(vertex.parents().len(), 4);
+            }
+            action => panic!("Unexpected action {:?}", action),
+        }
+        assert_eq!(core.current_round(), 2);
+    }
+
+    #[test]
+    fn test_vertex_waits_for_its_block() {
+        let committee = Committee::generate_from_seed(4, 0);
+        let mut core = ConsensusCore::new(1, committee.clone(), Box::new(Honest));
+        core.handle(Event::BlockAvailable(Block::new(vec![vec![1]])));
+        let block = Block::new(vec![vec![2]]);
+        let parents = core.state.dag.get_vertices(&1);
+        let vertex = Vertex::new(committee.get_node_key(2).unwrap(), 2, vec![block.hash()], parents);
+
+        // The block of the vertex is requested from its owner, and the vertex stays out of the DAG.
+        let actions = core.handle(Event::VertexReceived(vertex.clone()));
+        assert!(matches!(actions.as_slice(), [Action::RequestBlock(hash, 2)] if *hash == block.hash()));
+        assert!(!core.state.dag.contains_vertices(&[(vertex.hash(), 2)].into_iter().collect()));
+        assert!(core.handle(Event::BlockRequested(block.hash(), 3)).is_empty());
+        assert!(core.misses_blocks());
+
+        // Until it arrives, the block is requested again on every timer event.
+        let actions = core.handle(Event::TimerFired);
+        assert!(matches!(actions.as_slice(), [Action::RequestBlock(hash, 2)] if *hash == block.hash()));
+
+        // Once the block arrives, the vertex is added without its block being proposed again.
+        core.handle(Event::BlockAvailable(block.clone()));
+        assert!(core.state.dag.contains_vertices(&[(vertex.hash(), 2)].into_iter().collect()));
+        assert!(core.proposals.is_empty());
+        assert!(!core.misses_blocks());
+        assert!(core.handle(Event::TimerFired).is_empty());
+        let actions = core.handle(Event::BlockRequested(block.hash(), 3));
+        assert!(matches!(actions.as_slice(), [Action::SendBlock(sent, 3)] if sent.hash() == block.hash()));
+    }
+
+    #[test]
+    fn test_forged_block_is_dropped() {
+        let committee = Committee::generate_from_seed(4, 0);
+        let mut core = ConsensusCore::new(1, committee.clone(), Box::new(Honest));
+        core.handle(Event::BlockAvailable(Block::new(vec![vec![1]])));
+        let block = Block::new(vec![vec![2]]);
+        let parents = core.state.dag.get_vertices(&1);
+        let vertex = Vertex::new(committee.get_node_key(2).unwrap(), 2, vec![block.hash()], parents);
+        core.handle(Event::VertexReceived(vertex.clone()));
+
+        // Other transactions under the digest of the pending block are neither stored nor delivered.
+        let forged = Block { hash: block.hash(), transactions: vec![vec![3]] };
+        core.handle(Event::BlockReplied(forged.clone()));
+        core.handle(Event::BlockReceived(forged));
+        assert!(!core.state.has_block(&block.hash()));
+        assert!(!core.state.dag.contains_vertices(&[(vertex.hash(), 2)].into_iter().collect()));
+
+        // Nor is a block replied without being requested.
+        let unrequested = Block::new(vec![vec![4]]);
+        core.handle(Event::BlockReplied(unrequested.clone()));
+        assert!(!core.state.has_block(&unrequested.hash()));
+
+        core.handle(Event::BlockReplied(block.clone()));
+        assert_eq!(core.state.blocks[&block.hash()].transactions, block.transactions);
+        assert!(core.state.dag.contains_vertices(&[(vertex.hash(), 2)].into_iter().collect()));
+
+        // A block already stored is not replaced.
+        core.handle(Event::BlockReceived(Block { hash: block.hash(), transactions: vec![vec![3]] }));
+        assert_eq!(core.state.blocks[&block.hash()].transactions, block.transactions);
+    }
+
+    #[test]
+    fn test_block_is_proposed_once() {
+        let committee = Committee::generate_from_seed(4, 0);
+        let mut core = ConsensusCore::new(1, committee.clone(), Box::new(Honest));
+        let block = Block::new(vec![vec![1]]);
+        core.handle(Event::BlockAvailable(block.clone()));
+        assert_eq!(core.current_round(), 2);
+
+        // A block sent again is not queued a second time.
+        core.handle(Event::BlockAvailable(block));
+        assert!(core.proposals.is_empty());
+
+        // Nor is a queued block which another node proposes first.
+        let other = Block::new(vec![vec![2]]);
+        core.handle(Event::BlockAvailable(other.clone()));
+        assert_eq!(core.proposals.len(), 1);
+        let parents = core.state.dag.get_vertices(&1);
+        core.handle(Event::VertexReceived(Vertex::new(committee.get_node_key(2).unwrap(), 2, vec![other.hash()], parents)));
+        assert!(core.proposals.is_empty());
+    }
+
+    #[test]
+    fn test_dropping_node_recovers() {
+        let committee = Committee::generate_from_seed(4, 0);
+        let period = FaultPeriod { from_round: 2, until_round: Some(4), every: None, strategy: "drop".to_string() };
+        let adversary = parse_schedule(&[period], Box::new(Honest), 1, &committee).unwrap();
+        let mut core = ConsensusCore::new(1, committee.clone(), adversary);
+        core.handle(Event::BlockAvailable(Block::new(vec![vec![1]])));
+        assert!(core.drops_packets());
+
+        // The node stays in round 2, but the vertices it drops show the others moved on.
+        let vertex = |round: Round| {
+            let parents = [([round as u8; 32], round - 1)].into_iter().collect();
+            Vertex::new(committee.get_node_key(2).unwrap(), round, vec![], parents)
+        };
+        assert!(core.handle(Event::VertexReceived(vertex(3))).is_empty());
+        assert!(core.drops_packets());
+        core.handle(Event::VertexReceived(vertex(4)));
+        assert!(!core.drops_packets());
+        assert!(core.buffer.is_empty());
+        assert_eq!(core.current_round(), 2);
+    }
+
+    #[test]
+    fn test_vertex_of_round_zero_is_dropped() {
+        let committee = Committee::generate_from_seed(4, 0);
+        let mut core = ConsensusCore::new(1, committee.clone(), Box::new(Honest));
+        let vertex = Vertex::new(committee.get_node_key(2).unwrap(), 0, vec![], BTreeMap::new());
+
+        assert!(core.handle(Event::VertexReceived(vertex)).is_empty());
+        assert!(core.buffer.is_empty());
+    }
+
+    #[test]
+    fn test_vertex_not_following_its_parents_is_dropped() {
+        let committee = Committee::generate_from_seed(4, 0);
+        let mut core = ConsensusCore::new(1, committee.clone(), Box::new(Honest));
+        core.handle(Event::BlockAvailable(Block::new(vec![vec![1]])));
+        let mut parents = core.state.dag.get_vertices(&1);
+        let (hash, _) = parents.pop_first().unwrap();
+        parents.insert(hash, 2);
+        let vertex = Vertex::new(committee.get_node_key(2).unwrap(), 2, vec![], parents);
+
+        assert!(core.handle(Event::VertexReceived(vertex)).is_empty());
+        assert!(core.buffer.is_empty());
+    }
+}
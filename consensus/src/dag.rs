@@ -1,25 +1,23 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::{Display, format, Formatter};
 use std::ops::Add;
-use model::committee::NodePublicKey;
+use model::committee::{Committee, NodePublicKey, Stake};
 use model::Round;
 use model::vertex::{Vertex, VertexHash};
 use log::{debug};
 
 pub struct Dag {
     pub graph: BTreeMap<Round, HashMap<NodePublicKey, Vertex>>,
-    min_quorum: u32,
 }
 
 impl Dag {
-    pub fn new(root: Vec<Vertex>, min_quorum: u32) -> Self {
+    pub fn new(root: Vec<Vertex>) -> Self {
         let genesis = root
             .iter()
             .map(|v| (v.owner(), v.clone()))
             .collect::<HashMap<_, _>>();
         Dag {
             graph: [(1, genesis)].iter().cloned().collect(),
-            min_quorum,
         }
     }
 
@@ -46,22 +44,32 @@ impl Dag {
         }
     }
 
-    pub fn is_quorum_reached_for_round(&self, round: &Round) -> bool {
-        //debug!("round is:{}, length is:{}, min_quorum is:{}",round, self.graph.get(round).unwrap().len(), self.min_quorum);
+    /// The quorum is weighted by the stake in the committee of the round, which changes with the epochs.
+    pub fn is_quorum_reached_for_round(&self, round: &Round, committee: &Committee) -> bool {
+        //debug!("round is:{}, length is:{}, min_quorum is:{}",round, self.graph.get(round).unwrap().len(), committee.quorum_threshold());
         match self.graph.get(round) {
-            Some(v) => v.len() as u32 >= self.min_quorum,
+            Some(v) => v.keys().map(|owner| committee.stake_of(owner)).sum::<Stake>() >= committee.quorum_threshold(),
             None => false
         }
     }
 
-    pub fn is_linked_with_others_in_round(&self, vertex: &Vertex, round: Round) -> bool {
+    pub fn is_linked_with_others_in_round(&self, vertex: &Vertex, round: Round, committee: &Committee) -> bool {
         let mut weight = 0;
         for v in self.graph.get(&round).unwrap().values() {
             if self.is_strongly_linked(v, vertex) {
-                weight += 1;
+                weight += committee.stake_of(&v.owner());
             }
         }
-        weight >= self.min_quorum
+        weight >= committee.quorum_threshold()
+    }
+
+    /// Stake of the owners of the given vertices which are in the DAG.
+    pub fn get_stake(&self, vertices: &BTreeMap<VertexHash, Round>, committee: &Committee) -> Stake {
+        vertices
+            .iter()
+            .filter_map(|(hash, round)| self.get_vertex(*hash, round))
+            .map(|vertex| committee.stake_of(&vertex.owner()))
+            .sum()
     }
 
     pub fn get_valid_vertices_voters(&self, vertex: &Vertex, round: Round) -> Vec<NodePublicKey> {
@@ -89,12 +97,14 @@ impl Dag {
     fn is_linked_internal(&self, newest: &Vertex, oldest: &Vertex, get_parents: fn(&Vertex) -> BTreeMap<VertexHash, Round>) -> bool {
         if newest.round() > oldest.round() {
             let mut vertex_stack = vec![newest];
+            // Vertices reachable through several paths are only explored once.
+            let mut visited = HashSet::new();
             while !vertex_stack.is_empty() {
                 let vertex = vertex_stack.pop().unwrap();
                 for (parent, round) in get_parents(vertex) {
                     if parent == oldest.hash() {
                         return true;
-                    } else if round > oldest.round() {
+                    } else if round > oldest.round() && visited.insert(parent) {
                         if let Some(parent_vertex) = self.get_vertex(parent, &round) {
                             vertex_stack.push(parent_vertex)
                         }
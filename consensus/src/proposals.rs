@@ -0,0 +1,108 @@
+use std::collections::VecDeque;
+
+use model::block::BlockHash;
+use model::Round;
+
+use crate::core::MAX_WAVE;
+
+/// Limits on the blocks proposed by a vertex.
+#[derive(Clone, Copy, Debug)]
+pub struct ProposalBudget {
+    pub max_blocks: usize,
+    /// Total size of the transactions of the blocks. Batches of the workers are only known by their
+    /// digest and count as empty.
+    pub max_bytes: usize,
+}
+
+impl Default for ProposalBudget {
+    fn default() -> Self {
+        Self { max_blocks: 10, max_bytes: 500_000 }
+    }
+}
+
+#[derive(Debug)]
+struct Proposal {
+    block_hash: BlockHash,
+    size: usize,
+    /// Round in which the block was queued.
+    since: Round,
+}
+
+/// Blocks waiting to be proposed by this node, oldest first. A vertex always takes the oldest one,
+/// even if it is larger than the byte budget on its own, and fills the rest of its budget in the
+/// same order, passing over the blocks which do not fit. Blocks which have waited for a whole wave
+/// are overdue and taken regardless of the bytes left, so large blocks are not starved by smaller
+/// ones queued after them.
+#[derive(Debug)]
+pub struct ProposalQueue {
+    budget: ProposalBudget,
+    queue: VecDeque<Proposal>,
+}
+
+impl ProposalQueue {
+    pub fn new(budget: ProposalBudget) -> Self {
+        Self { budget, queue: VecDeque::new() }
+    }
+
+    pub fn set_budget(&mut self, budget: ProposalBudget) {
+        self.budget = budget;
+    }
+
+    pub fn push(&mut self, block_hash: BlockHash, size: usize, round: Round) {
+        self.queue.push_back(Proposal { block_hash, size, since: round });
+    }
+
+    /// Drop a block from the queue, once another vertex proposes it.
+    pub fn remove(&mut self, block_hash: &BlockHash) {
+        self.queue.retain(|proposal| proposal.block_hash != *block_hash);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Take the blocks of a vertex of `round`, within the budget.
+    pub fn take(&mut self, round: Round) -> Vec<BlockHash> {
+        let mut taken = Vec::new();
+        let mut bytes = 0;
+        self.queue.retain(|proposal| {
+            let overdue = round >= proposal.since + MAX_WAVE;
+            let fits = taken.is_empty() || overdue || bytes + proposal.size <= self.budget.max_bytes;
+            if taken.len() < self.budget.max_blocks.max(1) && fits {
+                taken.push(proposal.block_hash);
+                bytes += proposal.size;
+                false
+            } else {
+                true
+            }
+        });
+        taken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let mut queue = ProposalQueue::new(ProposalBudget { max_blocks: 3, max_bytes: 100 });
+        for (index, size) in [150, 60, 50, 30, 10, 10].into_iter().enumerate() {
+            queue.push([index as u8; 32], size, 2);
+        }
+
+        // The oldest block goes even if it is too large, and the others fill the vertex in order.
+        assert_eq!(queue.take(2), vec![[0; 32]]);
+        assert_eq!(queue.take(3), vec![[1; 32], [3; 32], [4; 32]]);
+        assert_eq!(queue.len(), 2);
+
+        // Blocks waiting for a wave are taken regardless of the bytes left.
+        queue.push([6; 32], 90, 3);
+        assert_eq!(queue.take(7), vec![[2; 32], [5; 32], [6; 32]]);
+        assert!(queue.is_empty());
+    }
+}
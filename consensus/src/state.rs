@@ -1,6 +1,7 @@
 use std::collections::{HashMap, HashSet};
 
 use model::Round;
+use model::block::{Block, BlockHash};
 use model::vertex::{Vertex, VertexHash};
 
 use crate::dag::Dag;
@@ -8,12 +9,18 @@ use crate::dag::Dag;
 pub struct State {
     pub current_round: Round,
     pub delivered_vertices: HashSet<VertexHash>,
+    /// Blocks and batches delivered with a committed vertex, which are not delivered again.
+    pub delivered_blocks: HashSet<BlockHash>,
     pub dag: Dag,
+    /// Blocks of the vertices, which are only added to the DAG once their block is here. The empty
+    /// block of the genesis vertices is always available.
+    pub blocks: HashMap<BlockHash, Block>,
+    /// Digests of the batches stored by the workers of this node, which count as available blocks.
+    pub batches: HashSet<BlockHash>,
 }
 
 impl State {
     pub fn new(genesis_vertices: Vec<Vertex>) -> Self {
-        let min_quorum = (2 * genesis_vertices.len() / 3 + 1) as u32;
         let genesis = genesis_vertices.clone()
             .iter()
             .map(|x| (x.owner(), x.clone()))
@@ -22,10 +29,18 @@ impl State {
         Self {
             current_round: 1,
             delivered_vertices: genesis.iter().map(|(_, v)| v.hash()).collect(),
-            dag: Dag::new(genesis_vertices.clone(), min_quorum),
+            delivered_blocks: HashSet::new(),
+            dag: Dag::new(genesis_vertices.clone()),
+            blocks: [(Block::default().hash(), Block::default())].into_iter().collect(),
+            batches: HashSet::new(),
         }
     }
 
+    /// Whether the block of this digest, or the batch of a worker, is available.
+    pub fn has_block(&self, block_hash: &BlockHash) -> bool {
+        self.blocks.contains_key(block_hash) || self.batches.contains(block_hash)
+    }
+
     pub fn set_vertex_as_delivered(&mut self, vertex_hash: VertexHash) {
         self.delivered_vertices.insert(vertex_hash);
     }
@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 
 pub type Transaction = Vec<u8>;
 pub type BlockHash = [u8; 32];
+pub type TransactionHash = [u8; 32];
 
 #[derive(Clone, Serialize, Deserialize, Default, Debug)]
 pub struct Block {
@@ -22,4 +23,15 @@ impl Block {
     pub fn hash(&self) -> BlockHash {
         self.hash
     }
-}
\ No newline at end of file
+
+    /// Whether the digest is the one of the transactions, which the digest of a block received from
+    /// another node need not be.
+    pub fn verify(&self) -> bool {
+        let encoded = bincode::serialize(&self.transactions).unwrap();
+        *blake3::hash(&encoded).as_bytes() == self.hash
+    }
+}
+
+pub fn transaction_hash(transaction: &Transaction) -> TransactionHash {
+    *blake3::hash(transaction).as_bytes()
+}
@@ -0,0 +1,183 @@
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Deserialize, Serialize};
+
+use crate::block::{Block, BlockHash};
+use crate::{Epoch, Error, Result};
+
+pub type MerkleRoot = [u8; 32];
+
+/// Erasure-coded piece of a block: one of the Reed-Solomon shards of the serialized block, with the
+/// Merkle proof that it belongs to the shards committed to by `root`. Any `data_shards` of the
+/// chunks of a block rebuild it.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Chunk {
+    pub block_hash: BlockHash,
+    /// Epoch of the builder of the block, whose committee gives the number of shards.
+    pub epoch: Epoch,
+    pub root: MerkleRoot,
+    pub index: u32,
+    pub data_shards: u32,
+    pub total_shards: u32,
+    /// Length of the serialized block, the last shard being padded.
+    pub length: u32,
+    pub data: Vec<u8>,
+    /// Siblings of the path from the shard to the root, from the bottom.
+    pub proof: Vec<[u8; 32]>,
+}
+
+/// Numbers of data shards and of shards in total for `node_count` nodes, or `None` if there are too
+/// few nodes for erasure coding. The `f` parity shards of the `3f + 1` nodes let the block be
+/// rebuilt from the chunks of any `2f + 1` of them, and there is at least one parity shard.
+pub fn shard_counts(node_count: usize) -> Option<(usize, usize)> {
+    if !(2..=256).contains(&node_count) {
+        return None;
+    }
+    let faulty = (node_count - 1) / 3;
+    Some(((node_count - faulty).min(node_count - 1), node_count))
+}
+
+fn leaf(index: u32, data: &[u8]) -> [u8; 32] {
+    *blake3::Hasher::new().update(&index.to_le_bytes()).update(data).finalize().as_bytes()
+}
+
+fn parent(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    *blake3::Hasher::new().update(left).update(right).finalize().as_bytes()
+}
+
+/// Levels of the Merkle tree of the leaves, from the leaves padded to a power of two up to the root.
+fn merkle_levels(leaves: Vec<[u8; 32]>) -> Vec<Vec<[u8; 32]>> {
+    let mut level = leaves;
+    level.resize(level.len().next_power_of_two(), [0; 32]);
+    let mut levels = vec![level];
+    while levels.last().unwrap().len() > 1 {
+        let next = levels.last().unwrap().chunks(2).map(|pair| parent(&pair[0], &pair[1])).collect();
+        levels.push(next);
+    }
+    levels
+}
+
+fn codec(data_shards: usize, total_shards: usize) -> Result<ReedSolomon> {
+    ReedSolomon::new(data_shards, total_shards - data_shards).map_err(|e| Error::ErasureError(e.to_string()))
+}
+
+impl Chunk {
+    /// Split the block into `total_shards` chunks, any `data_shards` of which rebuild it.
+    pub fn encode(block: &Block, epoch: Epoch, data_shards: usize, total_shards: usize) -> Result<Vec<Chunk>> {
+        let serialized = bincode::serialize(block)?;
+        let shard_size = serialized.len().div_ceil(data_shards).max(1);
+        let mut shards: Vec<Vec<u8>> = serialized.chunks(shard_size).map(<[u8]>::to_vec).collect();
+        shards.iter_mut().for_each(|shard| shard.resize(shard_size, 0));
+        shards.resize(total_shards, vec![0; shard_size]);
+        codec(data_shards, total_shards)?
+            .encode(&mut shards)
+            .map_err(|e| Error::ErasureError(e.to_string()))?;
+
+        let levels = merkle_levels((0..).zip(&shards).map(|(index, shard)| leaf(index, shard)).collect());
+        let root = levels.last().unwrap()[0];
+        Ok((0..).zip(shards)
+            .map(|(index, data)| Chunk {
+                block_hash: block.hash(),
+                epoch,
+                root,
+                index,
+                data_shards: data_shards as u32,
+                total_shards: total_shards as u32,
+                length: serialized.len() as u32,
+                data,
+                proof: levels[..levels.len() - 1].iter().enumerate()
+                    .map(|(height, level)| level[(index as usize >> height) ^ 1])
+                    .collect(),
+            })
+            .collect())
+    }
+
+    /// Whether the chunk has the shard counts of `node_count` receivers and its proof leads to its
+    /// root.
+    pub fn verify(&self, node_count: usize) -> bool {
+        if !self.has_shard_counts(node_count) || self.index >= self.total_shards {
+            return false;
+        }
+        let mut position = self.index as usize;
+        let mut hash = leaf(self.index, &self.data);
+        for sibling in &self.proof {
+            hash = match position % 2 {
+                0 => parent(&hash, sibling),
+                _ => parent(sibling, &hash),
+            };
+            position /= 2;
+        }
+        position == 0 && hash == self.root
+    }
+
+    fn has_shard_counts(&self, node_count: usize) -> bool {
+        shard_counts(node_count) == Some((self.data_shards as usize, self.total_shards as usize))
+    }
+
+    /// Rebuild the block of verified chunks sharing the same root, checking it has the digest the
+    /// chunks claim. The shard counts, which size the shards, must be the ones of `node_count`
+    /// receivers.
+    pub fn decode(chunks: &[Chunk], node_count: usize) -> Result<Block> {
+        let first = chunks.first().ok_or_else(|| Error::ErasureError("no chunk to decode".to_string()))?;
+        if !first.has_shard_counts(node_count) {
+            return Err(Error::ErasureError(format!("the chunks do not have the shard counts of {} nodes", node_count)));
+        }
+        let (data_shards, total_shards) = (first.data_shards as usize, first.total_shards as usize);
+        let mut shards: Vec<Option<Vec<u8>>> = vec![None; total_shards];
+        let matching = chunks.iter().filter(|chunk| {
+            chunk.root == first.root && chunk.index < first.total_shards && chunk.data.len() == first.data.len()
+        });
+        for chunk in matching {
+            shards[chunk.index as usize] = Some(chunk.data.clone());
+        }
+        codec(data_shards, total_shards)?
+            .reconstruct_data(&mut shards)
+            .map_err(|e| Error::ErasureError(e.to_string()))?;
+
+        let mut serialized: Vec<u8> = shards.into_iter().take(data_shards).flatten().flatten().collect();
+        serialized.truncate(first.length as usize);
+        let block: Block = bincode::deserialize(&serialized)?;
+        // The digest is recomputed, as the one of the serialized block is not to be trusted.
+        if Block::new(block.transactions.clone()).hash() != first.block_hash {
+            return Err(Error::ErasureError("the chunks do not rebuild the block they claim".to_string()));
+        }
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_erasure_coding() {
+        let block = Block::new((0..50).map(|i| vec![i; 40]).collect());
+        let (data_shards, total_shards) = shard_counts(7).unwrap();
+        assert_eq!((data_shards, total_shards), (5, 7));
+        let chunks = Chunk::encode(&block, 0, data_shards, total_shards).unwrap();
+        assert!(chunks.iter().all(|chunk| chunk.verify(7)));
+        // Each chunk is about a fifth of the block.
+        assert!(chunks[0].data.len() * 4 < bincode::serialized_size(&block).unwrap() as usize);
+
+        // Any five chunks rebuild the block.
+        let decoded = Chunk::decode(&chunks[2..], 7).unwrap();
+        assert_eq!(decoded.hash(), block.hash());
+        assert_eq!(decoded.transactions, block.transactions);
+        assert!(Chunk::decode(&chunks[3..], 7).is_err());
+
+        // A tampered chunk does not verify.
+        let mut tampered = chunks[1].clone();
+        tampered.data[0] ^= 1;
+        assert!(!tampered.verify(7));
+        tampered = chunks[1].clone();
+        tampered.index = 2;
+        assert!(!tampered.verify(7));
+
+        // Neither do chunks of another number of nodes, even with a valid proof, so a peer cannot
+        // make the receivers allocate shards for any count.
+        assert!(!chunks[1].verify(4));
+        assert!(Chunk::decode(&chunks[2..], 4).is_err());
+        let oversized = Chunk::encode(&block, 0, 134, 200).unwrap().swap_remove(0);
+        assert!(oversized.verify(200) && !oversized.verify(7));
+        assert!(Chunk::decode(&[oversized], 7).is_err());
+    }
+}
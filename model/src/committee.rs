@@ -1,44 +1,126 @@
 use std::collections::{HashMap};
-use std::net::SocketAddr;
-use ed25519_dalek::Keypair;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use ed25519_dalek::{Keypair, PublicKey};
+use rand::rngs::StdRng;
+use rand::{CryptoRng, RngCore, SeedableRng};
 use serde::{Serialize, Deserialize};
 
 
 pub type Id = u32;
 pub type NodePublicKey = [u8; 32];
+pub type Stake = u64;
+/// Index of a worker among the workers of its node, from 0.
+pub type WorkerId = u32;
 
-#[derive(Clone, Serialize, Deserialize)]
+fn default_stake() -> Stake {
+    1
+}
+
+/// First port of the generated committees, each node taking the next one.
+const FIRST_PORT: u16 = 8123;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Validator {
-    pub address: SocketAddr,
-    pub tx_address: SocketAddr,
-    pub block_address: SocketAddr,
+    /// Address the other nodes and the clients connect to, as `host:port` where the host is a name
+    /// or an IP.
+    pub address: String,
+    /// Address the node listens on, all interfaces on the port of the public address if missing.
+    #[serde(default)]
+    pub listen_address: Option<SocketAddr>,
     pub public_key: NodePublicKey,
+    /// Weight of the validator in quorums, leader election and ABSE scores, 1 if missing.
+    #[serde(default = "default_stake")]
+    pub stake: Stake,
+    /// Addresses of the workers of the node (`host:port`), which batch the transactions of the
+    /// clients and send them to the workers of the same index of the other nodes. They listen on all
+    /// interfaces.
+    #[serde(default)]
+    pub workers: Vec<String>,
+    /// Public address resolved by `resolve`.
+    #[serde(skip)]
+    resolved: Option<SocketAddr>,
+    /// Addresses of the workers resolved by `resolve`.
+    #[serde(skip)]
+    resolved_workers: Vec<SocketAddr>,
 }
 
 impl Validator {
-    pub fn new(keypair: &str, port: u16, tx_port: u16, block_port: u16) -> Self {
+    /// Validator reached at `host`, listening on all interfaces.
+    pub fn new(keypair: &str, host: &str, port: u16) -> Self {
         let keypair = Validator::create_keypair(String::from(keypair));
         let public_key = Validator::create_node_public_key_from(&keypair);
         Self {
-            address: SocketAddr::new("0.0.0.0".parse().unwrap(), port),
-            tx_address: SocketAddr::new("0.0.0.0".parse().unwrap(), tx_port),
-            block_address: SocketAddr::new("0.0.0.0".parse().unwrap(), block_port),
+            address: format!("{}:{}", host, port),
+            listen_address: None,
             public_key,
+            stake: default_stake(),
+            workers: vec![],
+            resolved: None,
+            resolved_workers: vec![],
         }
     }
 
+    /// Look up the hosts of the public addresses of the node and its workers, unless it was already
+    /// done.
+    pub fn resolve(&mut self) -> io::Result<()> {
+        if self.resolved.is_none() {
+            self.resolved = Some(resolve_address(&self.address)?);
+            self.resolved_workers = self.workers.iter().map(|address| resolve_address(address)).collect::<io::Result<_>>()?;
+        }
+        Ok(())
+    }
+
+    /// Public address, once resolved. Addresses with an IP do not need to be resolved.
+    pub fn socket_address(&self) -> Option<SocketAddr> {
+        self.resolved.or_else(|| self.address.parse().ok())
+    }
+
+    /// Address the node listens on.
+    pub fn bind_address(&self) -> SocketAddr {
+        self.listen_address.unwrap_or_else(|| any_interface(&self.address))
+    }
+
+    /// Public address of a worker, once resolved.
+    pub fn worker_address(&self, worker: WorkerId) -> Option<SocketAddr> {
+        let address = self.workers.get(worker as usize)?;
+        self.resolved_workers.get(worker as usize).copied().or_else(|| address.parse().ok())
+    }
+
+    /// Address a worker listens on.
+    pub fn worker_bind_address(&self, worker: WorkerId) -> Option<SocketAddr> {
+        self.workers.get(worker as usize).map(|address| any_interface(address))
+    }
+
     fn create_keypair(kps: String) -> Keypair {
         let bytes = hex::decode(kps).unwrap();
         return Keypair::from_bytes(&bytes).unwrap();
     }
 
     fn create_node_public_key_from(keypair: &Keypair) -> NodePublicKey {
-        let encoded = bincode::serialize(&keypair.public).unwrap();
+        Validator::node_public_key(&keypair.public)
+    }
+
+    /// Key identifying the validator holding an ed25519 public key, as found in the committee.
+    pub fn node_public_key(public: &PublicKey) -> NodePublicKey {
+        let encoded = bincode::serialize(public).unwrap();
         blake3::hash(&encoded).as_bytes().clone()
     }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+fn resolve_address(address: &str) -> io::Result<SocketAddr> {
+    address.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("No address found for {}", address))
+    })
+}
+
+/// All interfaces, on the port of a public address.
+fn any_interface(address: &str) -> SocketAddr {
+    let port = address.rsplit(':').next().and_then(|port| port.parse().ok()).unwrap_or(0);
+    SocketAddr::from(([0, 0, 0, 0], port))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Committee {
     pub validators: HashMap<Id, Validator>,
 }
@@ -48,16 +130,16 @@ impl Committee {
         let mut validators = HashMap::new();
         validators.insert(1, Validator::new(
             "ad7f2ee3958a7f3fa2c84931770f5773ef7694fdd0bb217d90f29a94199c9d7307ca3851515c89344639fe6a4077923068d1d7fc6106701213c61d34ef8e9416",
-            1234, 1244, 1254));
+            "127.0.0.1", 1234));
         validators.insert(2, Validator::new(
             "5a353c630d3faf8e2d333a0983c1c71d5e9b6aed8f4959578fbeb3d3f3172886393b576de0ac1fe86a4dd416cf032543ac1bd066eb82585f779f6ce21237c0cd",
-            1235, 1245, 1255));
+            "127.0.0.1", 1235));
         validators.insert(3, Validator::new(
             "6f4b736b9a6894858a81696d9c96cbdacf3d49099d212213f5abce33da18716f067f8a2b9aeb602cd4163291ebbf39e0e024634f3be19bde4c490465d9095a6b",
-            1236, 1246, 1256));
+            "127.0.0.1", 1236));
         validators.insert(4, Validator::new(
             "3ae38eec96146c241f6cadf01995af14f027b23b8fecbc77dbc2e3ed5fec6fc3fb4fe5534f7affc9a8f1d99e290fdb91cc26777edd6fae480cad9f735d1b3680",
-            1237, 1247, 1257));
+            "127.0.0.1", 1237));
 
         Self {
             validators
@@ -65,61 +147,168 @@ impl Committee {
     }
 
     pub fn generate(node_count: u32) -> Self {
+      Self::generate_with_rng(node_count, &["127.0.0.1".to_string()], &mut rand::thread_rng()).0
+    }
+
+    /// Generate a committee whose nodes are assigned to the hosts round-robin (node 1 to the first
+    /// one), along with the hex key pair of each node. Ports are unique across the committee, so
+    /// several nodes can share a host.
+    pub fn generate_on_hosts(node_count: u32, hosts: &[String]) -> (Self, HashMap<Id, String>) {
+      Self::generate_with_rng(node_count, hosts, &mut rand::thread_rng())
+    }
+
+    /// Generate a committee whose keys are derived from `seed`, so that the same seed always gives
+    /// the same committee (and therefore the same leader election).
+    pub fn generate_from_seed(node_count: u32, seed: u64) -> Self {
+      Self::generate_keys_from_seed(node_count, seed).0
+    }
+
+    /// Like `generate_from_seed`, along with the hex key pair of each node.
+    pub fn generate_keys_from_seed(node_count: u32, seed: u64) -> (Self, HashMap<Id, String>) {
+      Self::generate_with_rng(node_count, &["127.0.0.1".to_string()], &mut StdRng::seed_from_u64(seed))
+    }
+
+    fn generate_with_rng<R: CryptoRng + RngCore>(
+        node_count: u32,
+        hosts: &[String],
+        rng: &mut R,
+    ) -> (Self, HashMap<Id, String>) {
       let mut validators = HashMap::new();
+      let mut keypairs = HashMap::new();
 
       for id in 1..=node_count {
-          let keypair = Keypair::generate(&mut rand::thread_rng());
+          let keypair = Keypair::generate(rng);
           let kp_bytes = keypair.to_bytes();
           let kp_hex = hex::encode(kp_bytes);
 
-          // Assign port numbers for each type of address.
-          let port: u16 = 8123 + ((id as u16 - 1) * 3) as u16;
-          let tx_port: u16 = port + 1;
-          let block_port: u16 = port + 2;
+          let port: u16 = FIRST_PORT + (id as u16 - 1);
+          let host = &hosts[(id as usize - 1) % hosts.len()];
 
-          validators.insert(id, Validator::new(&kp_hex, port, tx_port, block_port));
+          validators.insert(id, Validator::new(&kp_hex, host, port));
+          keypairs.insert(id, kp_hex);
       }
 
-      Self { validators }
+      (Self { validators }, keypairs)
+    }
+
+    /// Give every node `count` workers on its host, on the ports following those of the nodes. Fails
+    /// if there are not enough ports left for all of them.
+    pub fn add_workers(&mut self, count: u32) -> Result<(), String> {
+        let port = |id: Id, worker: u32| {
+            let offset = u16::try_from(self.size()).ok()?;
+            let index = u16::try_from((id - 1).checked_mul(count)?.checked_add(worker)?).ok()?;
+            FIRST_PORT.checked_add(offset)?.checked_add(index)
+        };
+        let workers = self
+            .validators
+            .iter()
+            .map(|(id, validator)| {
+                let host = validator.address.rsplit_once(':').map_or("127.0.0.1", |(host, _)| host);
+                (0..count)
+                    .map(|worker| port(*id, worker).map(|port| format!("{}:{}", host, port)))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| format!("Not enough ports for {} workers of each of the {} nodes", count, self.size()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for (validator, workers) in self.validators.values_mut().zip(workers) {
+            validator.workers = workers;
+        }
+        Ok(())
+    }
+
+    /// Look up the hosts of the public addresses, once when the node starts.
+    pub fn resolve(&mut self) -> io::Result<()> {
+        self.validators.values_mut().try_for_each(Validator::resolve)
     }
 
     pub fn size(&self) -> usize {
         self.validators.len()
     }
 
-    pub fn quorum_threshold(&self) -> usize {
-        (self.size() * 2 / 3) + 1
+    pub fn total_stake(&self) -> Stake {
+        self.validators.values().map(|v| v.stake).sum()
     }
 
-    pub fn get_node_address(&self, id: Id) -> Option<SocketAddr> {
-        match self.validators.get(&id) {
-            Some(v) => Some(v.address),
-            None => None
+    /// Stake of the validator with this key, 0 if it is not in the committee.
+    pub fn stake_of(&self, key: &NodePublicKey) -> Stake {
+        self.validators.values().find(|v| v.public_key == *key).map_or(0, |v| v.stake)
+    }
+
+    /// Check the committee can elect leaders and form quorums: it needs validators, each with a
+    /// positive stake, and a total stake small enough to compute the quorum threshold.
+    pub fn check_stakes(&self) -> Result<(), String> {
+        if let Some((id, _)) = self.validators.iter().find(|(_, v)| v.stake == 0) {
+            return Err(format!("Validator {} has no stake", id));
         }
+        let total = self.validators.values().try_fold(0 as Stake, |total, v| total.checked_add(v.stake));
+        match total {
+            Some(0) => Err("The committee has no validator".to_string()),
+            Some(total) if total <= Stake::MAX / 2 => Ok(()),
+            _ => Err("The total stake of the committee is too large".to_string()),
+        }
+    }
+
+    /// Stake needed for a quorum, `n*2/3+1` when every validator has a stake of 1.
+    pub fn quorum_threshold(&self) -> Stake {
+        (self.total_stake() * 2 / 3) + 1
+    }
+
+    /// Leader elected by `coin`, with a probability proportional to its stake.
+    pub fn leader(&self, coin: u64) -> NodePublicKey {
+        let mut validators: Vec<_> = self.validators.values().collect();
+        validators.sort_by_key(|v| v.public_key);
+        let mut ticket = coin % self.total_stake();
+        for v in validators {
+            if ticket < v.stake {
+                return v.public_key;
+            }
+            ticket -= v.stake;
+        }
+        unreachable!("the ticket is below the total stake")
+    }
+
+    /// Public address of a node, if it is in the committee and its address is resolved.
+    pub fn get_node_address(&self, id: Id) -> Option<SocketAddr> {
+        self.validators.get(&id)?.socket_address()
     }
 
     pub fn get_node_addresses(&self) -> Vec<SocketAddr> {
-        self.validators.iter().map(|v| v.1.address).collect()
+        self.validators.values().filter_map(Validator::socket_address).collect()
     }
 
-    pub fn get_tx_receiver_address(&self, id: Id) -> Option<SocketAddr> {
-        self.validators.get(&id).map(|v| v.tx_address)
+    pub fn get_node_addresses_but_me(&self, id: Id) -> Vec<SocketAddr> {
+        self.validators
+            .iter()
+            .filter(|v| *v.0 != id)
+            .filter_map(|v| v.1.socket_address())
+            .collect()
     }
 
-    pub fn get_tx_receiver_addresses(&self) -> Vec<SocketAddr> {
-        self.validators.iter().map(|v| v.1.tx_address).collect()
+    /// Address a node listens on.
+    pub fn get_listen_address(&self, id: Id) -> Option<SocketAddr> {
+        self.validators.get(&id).map(Validator::bind_address)
     }
 
-    pub fn get_block_receiver_address(&self, id: Id) -> Option<SocketAddr> {
-        self.validators.get(&id).map(|v| v.block_address)
+    /// Public address of a worker of a node.
+    pub fn get_worker_address(&self, id: Id, worker: WorkerId) -> Option<SocketAddr> {
+        self.validators.get(&id)?.worker_address(worker)
     }
 
-    pub fn get_block_receiver_addresses(&self) -> Vec<SocketAddr> {
-        self.validators.iter().map(|v| v.1.block_address).collect()
+    /// Public addresses of the workers of the given index of every node which has one.
+    pub fn get_worker_addresses(&self, worker: WorkerId) -> Vec<SocketAddr> {
+        self.validators.values().filter_map(|v| v.worker_address(worker)).collect()
     }
 
-    pub fn get_node_addresses_but_me(&self, id: Id) -> Vec<SocketAddr> {
-        self.validators.iter().filter(|v| *v.0 != id).map(|v| v.1.address).collect()
+    /// Address a worker of a node listens on.
+    pub fn get_worker_listen_address(&self, id: Id, worker: WorkerId) -> Option<SocketAddr> {
+        self.validators.get(&id)?.worker_bind_address(worker)
+    }
+
+    /// Ids of the nodes, in increasing order.
+    pub fn get_node_ids(&self) -> Vec<Id> {
+        let mut ids: Vec<Id> = self.validators.keys().copied().collect();
+        ids.sort_unstable();
+        ids
     }
 
     pub fn get_nodes_keys(&self) -> Vec<NodePublicKey> {
@@ -129,6 +318,10 @@ impl Committee {
     pub fn get_node_key(&self, id: Id) -> Option<NodePublicKey> {
         self.validators.get(&id).map(|v| v.public_key)
     }
+
+    pub fn get_node_id(&self, key: &NodePublicKey) -> Option<Id> {
+        self.validators.iter().find(|(_, v)| v.public_key == *key).map(|(id, _)| *id)
+    }
 }
 
 
@@ -145,9 +338,87 @@ mod tests {
 
         // Check if the port numbers are assigned correctly.
         for (id, validator) in committee.validators {
-            assert_eq!(validator.address.port(), 8123 + ((id as u16 - 1) * 3));
-            assert_eq!(validator.tx_address.port(), 8124 + ((id as u16 - 1) * 3));
-            assert_eq!(validator.block_address.port(), 8125 + ((id as u16 - 1) * 3));
+            assert_eq!(validator.socket_address().unwrap().port(), 8123 + (id as u16 - 1));
+        }
+    }
+
+    #[test]
+    fn test_hosts_and_listen_addresses() {
+        let hosts = ["localhost".to_string(), "10.0.0.2".to_string()];
+        let (mut committee, keypairs) = Committee::generate_on_hosts(3, &hosts);
+        let keypair = Keypair::from_bytes(&hex::decode(&keypairs[&2]).unwrap()).unwrap();
+        let key = Validator::node_public_key(&keypair.public);
+        assert_eq!(committee.get_node_key(2), Some(key));
+        assert_eq!(committee.get_node_id(&key), Some(2));
+        assert_eq!(committee.validators[&1].address, "localhost:8123");
+        assert_eq!(committee.validators[&2].address, "10.0.0.2:8124");
+        assert_eq!(committee.validators[&3].address, "localhost:8125");
+
+        // Names are only usable once resolved, IPs right away.
+        assert_eq!(committee.get_node_address(1), None);
+        assert_eq!(committee.get_node_address(2), Some("10.0.0.2:8124".parse().unwrap()));
+        committee.resolve().unwrap();
+        assert_eq!(committee.get_node_address(1).unwrap().port(), 8123);
+
+        assert_eq!(committee.get_listen_address(2), Some("0.0.0.0:8124".parse().unwrap()));
+        committee.validators.get_mut(&2).unwrap().listen_address = Some("10.0.0.2:9000".parse().unwrap());
+        assert_eq!(committee.get_listen_address(2), Some("10.0.0.2:9000".parse().unwrap()));
+
+        // Committee files without listen addresses, or with the former transaction and block
+        // addresses, still load.
+        let json = r#"{"address":"0.0.0.0:1","tx_address":"0.0.0.0:2","block_address":"0.0.0.0:3","public_key":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0]}"#;
+        let validator: Validator = serde_json::from_str(json).unwrap();
+        assert_eq!(validator.bind_address(), "0.0.0.0:1".parse().unwrap());
+    }
+
+    #[test]
+    fn test_workers() {
+        let hosts = ["localhost".to_string(), "10.0.0.2".to_string()];
+        let (mut committee, _) = Committee::generate_on_hosts(3, &hosts);
+        committee.add_workers(2).unwrap();
+        // Workers run on the host of their node, after the ports of the nodes.
+        assert_eq!(committee.validators[&1].workers, ["localhost:8126", "localhost:8127"]);
+        assert_eq!(committee.get_worker_address(2, 1), Some("10.0.0.2:8129".parse().unwrap()));
+        assert_eq!(committee.get_worker_address(2, 2), None);
+        assert_eq!(committee.get_worker_listen_address(3, 0), Some("0.0.0.0:8130".parse().unwrap()));
+        committee.resolve().unwrap();
+        assert_eq!(committee.get_worker_addresses(0).len(), 3);
+
+        // The ports of the workers do not wrap around.
+        assert!(committee.add_workers(20_000).is_err());
+        assert_eq!(committee.get_worker_address(2, 1), Some("10.0.0.2:8129".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_stake_weighted_committee() {
+        let mut committee = Committee::generate_from_seed(4, 0);
+        assert_eq!(committee.quorum_threshold(), 3);
+        committee.validators.get_mut(&1).unwrap().stake = 4;
+        assert_eq!(committee.quorum_threshold(), 5);
+
+        // The first validator by key, which holds 4 of the 7 tickets, or another one.
+        let key = committee.get_node_key(1).unwrap();
+        let elected = (0..7).filter(|coin| committee.leader(*coin) == key).count();
+        assert_eq!(elected, 4);
+
+        // Leaders are drawn modulo the total stake, which must be positive.
+        assert!(committee.check_stakes().is_ok());
+        committee.validators.get_mut(&2).unwrap().stake = 0;
+        assert!(committee.check_stakes().is_err());
+        committee.validators.get_mut(&2).unwrap().stake = Stake::MAX;
+        assert!(committee.check_stakes().is_err());
+        assert!(Committee { validators: HashMap::new() }.check_stakes().is_err());
+    }
+
+    #[test]
+    fn test_committee_generate_from_seed() {
+        let first = Committee::generate_from_seed(4, 42);
+        let second = Committee::generate_from_seed(4, 42);
+
+        // The same seed gives the same keys.
+        for id in 1..=4 {
+            assert_eq!(first.get_node_key(id), second.get_node_key(id));
         }
+        assert_ne!(first.get_node_key(1), first.get_node_key(2));
     }
 }
\ No newline at end of file
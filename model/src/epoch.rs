@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer as _, Verifier as _};
+use serde::{Deserialize, Serialize};
+
+use crate::block::Transaction;
+use crate::committee::{Committee, NodePublicKey, Stake, Validator};
+use crate::{Epoch, Round};
+
+/// Prefix of the transactions carrying a `Reconfiguration`. Sample transactions start with 0, so
+/// they are never mistaken for one.
+const RECONFIGURATION_PREFIX: &[u8] = b"reconfigure";
+
+/// Transaction replacing the committee. Once committed, the new committee takes over at a wave
+/// every node derives from the commit (see `ConsensusCore`), provided validators holding a quorum of
+/// the stake of the committee it replaces signed it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Reconfiguration {
+    /// Epoch started by the new committee, the one following the last scheduled epoch.
+    pub epoch: Epoch,
+    pub committee: Committee,
+    /// Signatures of `signed_bytes` by validators of the committee being replaced.
+    pub signatures: Vec<(PublicKey, Signature)>,
+}
+
+impl Reconfiguration {
+    /// Reconfiguration signed by nobody yet.
+    pub fn new(epoch: Epoch, committee: Committee) -> Self {
+        Self { epoch, committee, signatures: vec![] }
+    }
+
+    /// Bytes covered by the signatures: the prefix, the epoch and the committee with its validators
+    /// in the order of their ids, so that every node encodes it the same way.
+    fn signed_bytes(&self) -> Vec<u8> {
+        let validators: BTreeMap<_, _> = self.committee.validators.iter().collect();
+        let encoded = bincode::serialize(&(self.epoch, validators)).expect("Failed to serialize the committee");
+        [RECONFIGURATION_PREFIX, &encoded].concat()
+    }
+
+    pub fn sign(&mut self, keypair: &Keypair) {
+        let signature = keypair.sign(&self.signed_bytes());
+        self.signatures.push((keypair.public, signature));
+    }
+
+    /// Stake of the validators of `current` with a valid signature, each counted once.
+    pub fn signed_stake(&self, current: &Committee) -> Stake {
+        let signed = self.signed_bytes();
+        let signers: HashSet<NodePublicKey> = self
+            .signatures
+            .iter()
+            .filter(|(public, signature)| public.verify(&signed, signature).is_ok())
+            .map(|(public, _)| Validator::node_public_key(public))
+            .collect();
+        signers.iter().map(|key| current.stake_of(key)).sum()
+    }
+
+    /// Whether validators holding a quorum of the stake of `current` signed the reconfiguration.
+    pub fn is_authorized(&self, current: &Committee) -> bool {
+        self.signed_stake(current) >= current.quorum_threshold()
+    }
+
+    pub fn to_transaction(&self) -> Transaction {
+        let encoded = bincode::serialize(self).expect("Failed to serialize the reconfiguration");
+        [RECONFIGURATION_PREFIX, &encoded].concat()
+    }
+
+    /// Decode a transaction, if it carries a reconfiguration to a committee passing
+    /// `Committee::check_stakes`. The signatures are not checked.
+    pub fn from_transaction(transaction: &[u8]) -> Option<Self> {
+        let encoded = transaction.strip_prefix(RECONFIGURATION_PREFIX)?;
+        bincode::deserialize::<Self>(encoded)
+            .ok()
+            .filter(|reconfiguration| reconfiguration.committee.check_stakes().is_ok())
+    }
+}
+
+/// Committees of the epochs scheduled so far, each one in charge from its first round on, and the
+/// round reached by the node.
+#[derive(Clone)]
+pub struct Epochs {
+    committees: BTreeMap<Round, (Epoch, Committee)>,
+    round: Round,
+}
+
+impl Epochs {
+    /// Epoch 0, starting with the genesis round.
+    pub fn new(committee: Committee) -> Self {
+        Self {
+            committees: [(0, (0, committee))].into_iter().collect(),
+            round: 1,
+        }
+    }
+
+    pub fn committee_at(&self, round: Round) -> &Committee {
+        &self.at(round).1
+    }
+
+    pub fn epoch_at(&self, round: Round) -> Epoch {
+        self.at(round).0
+    }
+
+    /// Committee of an epoch, if it is scheduled.
+    pub fn committee_of(&self, epoch: Epoch) -> Option<&Committee> {
+        self.committees.values().find(|(scheduled, _)| *scheduled == epoch).map(|(_, committee)| committee)
+    }
+
+    /// First round of the epoch of `round`, 0 for the first epoch.
+    pub fn first_round_at(&self, round: Round) -> Round {
+        *self.committees.range(..=round).next_back().unwrap().0
+    }
+
+    fn at(&self, round: Round) -> &(Epoch, Committee) {
+        self.committees.range(..=round).next_back().map(|(_, epoch)| epoch).unwrap()
+    }
+
+    /// Committee of the round reached by the node.
+    pub fn current(&self) -> &Committee {
+        self.committee_at(self.round)
+    }
+
+    /// Epoch of the round reached by the node.
+    pub fn current_epoch(&self) -> Epoch {
+        self.epoch_at(self.round)
+    }
+
+    /// Committee of the round reached by the node, followed by those of the epochs scheduled after it.
+    pub fn current_and_next(&self) -> impl Iterator<Item = &Committee> {
+        let first = self.committees.range(..=self.round).next_back().map(|(round, _)| *round).unwrap();
+        self.committees.range(first..).map(|(_, (_, committee))| committee)
+    }
+
+    pub fn last_epoch(&self) -> Epoch {
+        self.committees.values().next_back().unwrap().0
+    }
+
+    /// Committee of the last scheduled epoch, which signs the reconfiguration to the next one.
+    pub fn last_committee(&self) -> &Committee {
+        &self.committees.values().next_back().unwrap().1
+    }
+
+    pub fn round(&self) -> Round {
+        self.round
+    }
+
+    pub fn advance_to(&mut self, round: Round) {
+        self.round = round;
+    }
+
+    /// Look up the hosts of the committees, see `Committee::resolve`.
+    pub fn resolve(&mut self) -> io::Result<()> {
+        self.committees.values_mut().try_for_each(|(_, committee)| committee.resolve())
+    }
+
+    /// Hand over to the committee of `epoch` from `first_round` on.
+    pub fn schedule(&mut self, first_round: Round, epoch: Epoch, committee: Committee) {
+        self.committees.insert(first_round, (epoch, committee));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epochs() {
+        let mut epochs = Epochs::new(Committee::generate_from_seed(4, 0));
+        epochs.schedule(9, 1, Committee::generate_from_seed(3, 0));
+        assert_eq!(epochs.committee_at(8).size(), 4);
+        assert_eq!(epochs.committee_at(9).size(), 3);
+        assert_eq!((epochs.first_round_at(8), epochs.first_round_at(12)), (0, 9));
+        assert_eq!((epochs.epoch_at(1), epochs.epoch_at(20), epochs.last_epoch()), (0, 1, 1));
+        assert_eq!(epochs.current_and_next().count(), 2);
+        assert_eq!(epochs.committee_of(1).map(Committee::size), Some(3));
+        assert!(epochs.committee_of(2).is_none());
+        epochs.advance_to(9);
+        assert_eq!(epochs.current_epoch(), 1);
+        assert_eq!(epochs.current().size(), 3);
+        assert_eq!(epochs.current_and_next().count(), 1);
+    }
+
+    #[test]
+    fn test_reconfiguration_transaction() {
+        let reconfiguration = Reconfiguration::new(1, Committee::generate_from_seed(3, 0));
+        let decoded = Reconfiguration::from_transaction(&reconfiguration.to_transaction()).unwrap();
+        assert_eq!((decoded.epoch, decoded.committee.size()), (1, 3));
+        assert!(Reconfiguration::from_transaction(&[0, 1, 2]).is_none());
+
+        // Committees without stake are rejected.
+        let mut committee = Committee::generate_from_seed(3, 0);
+        committee.validators.get_mut(&1).unwrap().stake = 0;
+        assert!(Reconfiguration::from_transaction(&Reconfiguration::new(1, committee).to_transaction()).is_none());
+    }
+
+    #[test]
+    fn test_reconfiguration_signatures() {
+        let (current, keypairs) = Committee::generate_keys_from_seed(4, 0);
+        let keypair = |id| Keypair::from_bytes(&hex::decode(&keypairs[&id]).unwrap()).unwrap();
+        let mut reconfiguration = Reconfiguration::new(1, Committee::generate_from_seed(3, 1));
+        reconfiguration.sign(&keypair(1));
+        reconfiguration.sign(&keypair(2));
+        // Signing twice does not count twice.
+        reconfiguration.sign(&keypair(2));
+        assert_eq!(reconfiguration.signed_stake(&current), 2);
+        assert!(!reconfiguration.is_authorized(&current));
+
+        reconfiguration.sign(&keypair(3));
+        let decoded = Reconfiguration::from_transaction(&reconfiguration.to_transaction()).unwrap();
+        assert!(decoded.is_authorized(&current));
+
+        // The signatures cover the committee and the epoch.
+        let mut forged = decoded.clone();
+        forged.epoch = 2;
+        assert_eq!(forged.signed_stake(&current), 0);
+        let mut forged = decoded;
+        forged.committee.validators.get_mut(&1).unwrap().stake = 10;
+        assert!(!forged.is_authorized(&current));
+    }
+}
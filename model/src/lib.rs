@@ -4,6 +4,7 @@ pub const DEFAULT_CHANNEL_CAPACITY: usize = 1_000;
 
 pub type Round = u64;
 pub type Wave = u64;
+pub type Epoch = u64;
 
 pub type Result<T> = core::result::Result<T, Error>;
 
@@ -12,10 +13,17 @@ pub enum Error {
     #[error("Serialization error: {0}")]
     SerializationError(#[from] Box<bincode::ErrorKind>),
 
+    #[error("Erasure coding error: {0}")]
+    ErasureError(String),
+
     #[error("UnexpectedError {0}")]
     UnexpectedError(String),
 }
 
 pub mod vertex;
 pub mod block;
+pub mod chunk;
 pub mod committee;
+pub mod epoch;
+pub mod message;
+pub mod transaction;
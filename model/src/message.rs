@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+use crate::block::{Block, BlockHash, Transaction};
+use crate::chunk::Chunk;
+use crate::committee::Id;
+use crate::transaction::CommitPosition;
+use crate::vertex::Vertex;
+
+/// Everything sent to the port of a node, by the other nodes or by the clients. The variants are
+/// encoded by their index, so new ones go at the end, along with a bump of the protocol version
+/// (`network::PROTOCOL_VERSION`).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Message {
+    /// A vertex, sent by its owner.
+    Vertex(Vertex),
+    /// A block of transactions, sent by a node to the ones which propose it, or to a node missing
+    /// the block of a vertex.
+    Block(Block),
+    /// A transaction submitted by a client.
+    Transaction(Transaction),
+    /// Command of an operator, for nodes accepting them.
+    Admin(AdminCommand),
+    /// Request of the given node for the block of a vertex, sent to the owner of the vertex.
+    BlockRequest(BlockHash, Id),
+    /// Digest of a batch stored by a worker, sent to its node, which proposes it if the worker
+    /// sealed it (`true`) and otherwise only knows it is available.
+    Batch(BlockHash, bool),
+    /// Erasure-coded chunk of a block, sent by the builder of the block to the node of the index of
+    /// the chunk, which echoes it to the other nodes.
+    Chunk(Chunk),
+    /// Batches committed by a node at the given position, sent to each of its workers, which reply
+    /// with the blocks of those it stores, encoded as a `Vec<Block>`.
+    Committed(Vec<BlockHash>, CommitPosition),
+    /// Block of a vertex, sent by the owner of the vertex to the node which requested it.
+    BlockReply(Block),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AdminCommand {
+    /// Switch to a partition like `1,2|3,4`, or heal the network if there is none.
+    Partition(Option<String>),
+}
+
+impl Message {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Failed to serialize a message")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_encoding() {
+        let message = Message::Admin(AdminCommand::Partition(Some("1,2|3,4".to_string())));
+        match Message::from_bytes(&message.to_bytes()).unwrap() {
+            Message::Admin(command) => assert_eq!(command, AdminCommand::Partition(Some("1,2|3,4".to_string()))),
+            other => panic!("Unexpected message {:?}", other),
+        }
+
+        // A bare transaction is not a message.
+        assert!(Message::from_bytes(&[0, 1, 2]).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer as _, Verifier as _};
+use serde::{Deserialize, Serialize};
+
+use crate::block::{Transaction, TransactionHash};
+use crate::Wave;
+
+/// Prefix of the transactions carrying a `SignedTransaction`. Sample transactions start with 0 and
+/// reconfigurations with `reconfigure`, so they are never mistaken for one.
+const SIGNED_PREFIX: &[u8] = b"signed";
+
+/// Transaction of a client, signed with its key. Nonces increase with every transaction of the
+/// client, so that nodes can reject replayed ones.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SignedTransaction {
+    pub client: PublicKey,
+    pub nonce: u64,
+    pub payload: Vec<u8>,
+    pub signature: Signature,
+}
+
+impl SignedTransaction {
+    pub fn new(keypair: &Keypair, nonce: u64, payload: Vec<u8>) -> Self {
+        let signature = keypair.sign(&Self::signed_bytes(&keypair.public, nonce, &payload));
+        Self { client: keypair.public, nonce, payload, signature }
+    }
+
+    /// Bytes covered by the signature, which commit to every other field.
+    fn signed_bytes(client: &PublicKey, nonce: u64, payload: &[u8]) -> Vec<u8> {
+        [SIGNED_PREFIX, client.as_bytes(), &nonce.to_le_bytes(), payload].concat()
+    }
+
+    pub fn verify(&self) -> bool {
+        let signed = Self::signed_bytes(&self.client, self.nonce, &self.payload);
+        self.client.verify(&signed, &self.signature).is_ok()
+    }
+
+    /// Canonical encoding: the prefix followed by the fields in order, as fixed-size integers.
+    pub fn to_transaction(&self) -> Transaction {
+        let encoded = bincode::serialize(self).expect("Failed to serialize the transaction");
+        [SIGNED_PREFIX, &encoded].concat()
+    }
+
+    /// Decode a transaction, if it carries a signed transaction in its canonical encoding. The
+    /// signature is not checked.
+    pub fn from_transaction(transaction: &[u8]) -> Option<Self> {
+        let encoded = transaction.strip_prefix(SIGNED_PREFIX)?;
+        bincode::deserialize::<Self>(encoded)
+            .ok()
+            .filter(|signed| bincode::serialized_size(signed).ok() == Some(encoded.len() as u64))
+    }
+}
+
+/// Size of the header of the sample transactions of the client: 0, the counter identifying the
+/// transaction and the time it was sent at in ms since the Unix epoch, as big-endian integers.
+pub const SAMPLE_HEADER_SIZE: usize = 17;
+
+/// Time a sample transaction was sent at, in ms since the Unix epoch, whether it is signed or not.
+pub fn sample_send_time(transaction: &[u8]) -> Option<u64> {
+    let signed = SignedTransaction::from_transaction(transaction);
+    let payload = signed.as_ref().map_or(transaction, |signed| &signed.payload);
+    if payload.len() < SAMPLE_HEADER_SIZE || payload[0] != 0 {
+        return None;
+    }
+    Some(u64::from_be_bytes(payload[9..SAMPLE_HEADER_SIZE].try_into().unwrap()))
+}
+
+/// Position of a committed vertex in the total order, the same at every node.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CommitPosition {
+    /// Wave whose leader committed the vertex.
+    pub wave: Wave,
+    /// Number of vertices committed before it.
+    pub sequence: u64,
+}
+
+/// What a node tells a client about a transaction it submitted, over the connection of the
+/// transaction.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Receipt {
+    /// The transaction waits for room in the mempool of the node.
+    Queued(TransactionHash),
+    Accepted(TransactionHash),
+    Rejected(TransactionHash, String),
+    /// A vertex carrying the transaction was delivered by the consensus. Only the nodes send it, as
+    /// the workers do not see the commits.
+    Committed(TransactionHash, CommitPosition),
+}
+
+impl Receipt {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Failed to serialize a receipt")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::SecretKey;
+
+    use super::*;
+
+    #[test]
+    fn test_signed_transaction() {
+        let secret = SecretKey::from_bytes(&[7; 32]).unwrap();
+        let keypair = Keypair { public: PublicKey::from(&secret), secret };
+        let signed = SignedTransaction::new(&keypair, 3, vec![1, 2, 3]);
+        assert!(signed.verify());
+
+        let transaction = signed.to_transaction();
+        assert_eq!(SignedTransaction::from_transaction(&transaction), Some(signed.clone()));
+        // Trailing bytes would give the same transaction another hash.
+        assert_eq!(SignedTransaction::from_transaction(&[transaction.as_slice(), &[0]].concat()), None);
+        assert_eq!(SignedTransaction::from_transaction(&[0, 1, 2]), None);
+
+        let sample = [&[0][..], &7u64.to_be_bytes(), &42u64.to_be_bytes(), &[1, 2]].concat();
+        assert_eq!(sample_send_time(&sample), Some(42));
+        assert_eq!(sample_send_time(&SignedTransaction::new(&keypair, 0, sample).to_transaction()), Some(42));
+        assert_eq!(sample_send_time(&signed.to_transaction()), None);
+
+        let mut replayed = signed;
+        replayed.nonce = 4;
+        assert!(!replayed.verify());
+    }
+}
@@ -1,19 +1,35 @@
 use std::collections::{BTreeMap};
 use std::fmt;
 use serde::{Deserialize, Serialize};
-use crate::block::Block;
-use crate::committee::NodePublicKey;
+use crate::block::{Block, BlockHash};
+use crate::committee::{Id, NodePublicKey};
 use crate::Round;
 
 pub type VertexHash = [u8; 32];
 
+/// A vertex handed by consensus to the broadcaster, with the nodes it should reach.
+#[derive(Clone, Debug)]
+pub enum VertexMessage {
+    /// Send the vertex to every node of the committee.
+    Broadcast(Vertex),
+    /// Send the vertex to the given nodes only.
+    Send(Vertex, Vec<Id>),
+    /// Send raw bytes, which do not decode as a vertex, to the given nodes.
+    Malformed(Vec<u8>, Vec<Id>),
+    /// Ask the given node for the block of one of its vertices, missing from this node.
+    RequestBlock(BlockHash, Id),
+    /// Send a block to the node which requested it.
+    SendBlock(Block, Id),
+}
+
 #[derive(Clone, Serialize, Deserialize, Default)]
 pub struct Vertex {
     /// Vertex unique identifier
     hash: VertexHash,
     /// source of the header (the node which created it)
     owner: NodePublicKey,
-    block: Block,
+    /// Digests of the blocks proposed by the vertex, which travel separately.
+    block_hashes: Vec<BlockHash>,
     parents: BTreeMap<VertexHash, Round>,
     round: Round,
 }
@@ -21,13 +37,13 @@ pub struct Vertex {
 impl Vertex {
     pub fn new(owner: NodePublicKey,
                round: Round,
-               block: Block,
+               block_hashes: Vec<BlockHash>,
                parents: BTreeMap<VertexHash, Round>,
     ) -> Self {
         let vertex = Self {
             owner,
             round,
-            block,
+            block_hashes,
             parents,
             hash: VertexHash::default(),
         };
@@ -40,7 +56,7 @@ impl Vertex {
     }
 
     pub fn genesis(nodes: Vec<NodePublicKey>) -> Vec<Self> {
-        nodes.iter().map(|owner| Vertex::new(*owner, 1, Block::default(), BTreeMap::new())).collect()
+        nodes.iter().map(|owner| Vertex::new(*owner, 1, vec![], BTreeMap::new())).collect()
     }
 
     pub fn add_parent(&mut self, parent_vertex_hash: VertexHash, round: Round) {
@@ -74,6 +90,10 @@ impl Vertex {
         &self.parents
     }
 
+    pub fn block_hashes(&self) -> &[BlockHash] {
+        &self.block_hashes
+    }
+
     pub fn owner(&self) -> NodePublicKey {
         self.owner
     }
@@ -83,7 +103,7 @@ impl Vertex {
     }
 
     fn is_previous_round(&self, previous_round: &Round) -> bool {
-        self.round - previous_round == 1
+        previous_round + 1 == self.round
     }
 }
 
@@ -0,0 +1,323 @@
+use crate::codec::{Cipher, Codec};
+use crate::error::NetworkError;
+use bytes::Bytes;
+use curve25519_dalek::constants::X25519_BASEPOINT;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::Scalar;
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer as _, Verifier as _};
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use rand::Rng as _;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::{timeout, Duration};
+use tokio_util::codec::Framed;
+
+/// A peer taking longer than this to complete the handshake is dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Context of the session key derivation (see `blake3::derive_key`).
+const SESSION_CONTEXT: &str = "dag-rider 2024 handshake session key";
+
+/// Id of a peer, as given by the `Authenticator` of the node.
+pub type PeerId = u32;
+
+/// Gives the id of the peer holding a public key, if it may connect.
+type Authorize = dyn Fn(&PublicKey) -> Option<PeerId> + Send + Sync;
+
+/// Outcome of a successful handshake.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    /// The authenticated peer.
+    pub peer: PeerId,
+    /// Key shared with the peer, derived from an ephemeral Diffie-Hellman exchange.
+    pub key: [u8; 32],
+    /// Whether the frames of the connection are encrypted from now on.
+    pub encrypted: bool,
+}
+
+/// Key pair of the node and the peers it accepts, which authenticate every connection between two
+/// nodes with a challenge-response handshake:
+///  1. the connecting side sends its public key, a nonce and an ephemeral x25519 key,
+///  2. the accepting side answers with its own ones and a signature of the handshake so far,
+///  3. the connecting side checks it and sends its own signature of the handshake.
+///
+/// Both sides then derive the same session key from the ephemeral keys, and encrypt the rest of the
+/// connection with it if either of them asks for it. Peers whose public key is not accepted are rejected.
+#[derive(Clone)]
+pub struct Authenticator {
+    keypair: Arc<Keypair>,
+    authorize: Arc<Authorize>,
+    encrypt: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Hello {
+    public_key: PublicKey,
+    nonce: [u8; 32],
+    ephemeral_key: [u8; 32],
+    encrypt: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Challenge {
+    hello: Hello,
+    signature: Signature,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Response {
+    signature: Signature,
+}
+
+/// Ephemeral x25519 key of one side of a handshake.
+struct Ephemeral {
+    secret: Scalar,
+    hello: Hello,
+}
+
+impl Authenticator {
+    pub fn new(keypair: Keypair, authorize: impl Fn(&PublicKey) -> Option<PeerId> + Send + Sync + 'static) -> Self {
+        Self {
+            keypair: Arc::new(keypair),
+            authorize: Arc::new(authorize),
+            encrypt: false,
+        }
+    }
+
+    /// Ask for the connections to be encrypted after the handshake.
+    pub fn with_encryption(mut self, encrypt: bool) -> Self {
+        self.encrypt = encrypt;
+        self
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.keypair.public
+    }
+
+    fn hello(&self) -> Ephemeral {
+        let mut rng = rand::thread_rng();
+        let mut secret = rng.gen::<[u8; 32]>();
+        // Clamp the secret as x25519 does.
+        secret[0] &= 248;
+        secret[31] &= 127;
+        secret[31] |= 64;
+        let secret = Scalar::from_bits(secret);
+        Ephemeral {
+            hello: Hello {
+                public_key: self.keypair.public,
+                nonce: rng.gen(),
+                ephemeral_key: (X25519_BASEPOINT * secret).to_bytes(),
+                encrypt: self.encrypt,
+            },
+            secret,
+        }
+    }
+
+    /// Run the handshake on a new outgoing connection to `address`.
+    pub async fn connect<T: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        transport: &mut Framed<T, Codec>,
+        address: SocketAddr,
+    ) -> Result<Session, NetworkError> {
+        timeout(HANDSHAKE_TIMEOUT, self.run_connect(transport, address))
+            .await
+            .unwrap_or_else(|_| Err(NetworkError::FailedHandshake(address, "timeout".to_string())))
+    }
+
+    async fn run_connect<T: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        transport: &mut Framed<T, Codec>,
+        address: SocketAddr,
+    ) -> Result<Session, NetworkError> {
+        let ephemeral = self.hello();
+        send(transport, &ephemeral.hello, address).await?;
+
+        let challenge: Challenge = receive(transport, address).await?;
+        let peer = (self.authorize)(&challenge.hello.public_key).ok_or(NetworkError::UnknownPeer(address))?;
+        let transcript = transcript(&ephemeral.hello, &challenge.hello);
+        challenge
+            .hello
+            .public_key
+            .verify(&signed(b"accept", &transcript), &challenge.signature)
+            .map_err(|_| NetworkError::FailedHandshake(address, "invalid signature".to_string()))?;
+
+        let signature = self.keypair.sign(&signed(b"connect", &transcript));
+        send(transport, &Response { signature }, address).await?;
+        let session = session(peer, &ephemeral, &challenge.hello, &transcript, address)?;
+        if session.encrypted {
+            transport.codec_mut().set_cipher(Cipher::new(&session.key, /* connecting */ true));
+        }
+        Ok(session)
+    }
+
+    /// Run the handshake on a new incoming connection from `address`.
+    pub async fn accept<T: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        transport: &mut Framed<T, Codec>,
+        address: SocketAddr,
+    ) -> Result<Session, NetworkError> {
+        timeout(HANDSHAKE_TIMEOUT, self.run_accept(transport, address))
+            .await
+            .unwrap_or_else(|_| Err(NetworkError::FailedHandshake(address, "timeout".to_string())))
+    }
+
+    async fn run_accept<T: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        transport: &mut Framed<T, Codec>,
+        address: SocketAddr,
+    ) -> Result<Session, NetworkError> {
+        let hello: Hello = receive(transport, address).await?;
+        let peer = (self.authorize)(&hello.public_key).ok_or(NetworkError::UnknownPeer(address))?;
+
+        let ephemeral = self.hello();
+        let transcript = transcript(&hello, &ephemeral.hello);
+        let signature = self.keypair.sign(&signed(b"accept", &transcript));
+        let session = session(peer, &ephemeral, &hello, &transcript, address)?;
+        send(transport, &Challenge { hello: ephemeral.hello, signature }, address).await?;
+
+        let response: Response = receive(transport, address).await?;
+        hello
+            .public_key
+            .verify(&signed(b"connect", &transcript), &response.signature)
+            .map_err(|_| NetworkError::FailedHandshake(address, "invalid signature".to_string()))?;
+        if session.encrypted {
+            transport.codec_mut().set_cipher(Cipher::new(&session.key, /* connecting */ false));
+        }
+        Ok(session)
+    }
+}
+
+/// Digest of the keys and nonces sent by both sides, which each side signs with a role of its own, so
+/// that a signature cannot be replayed by the other side or in another handshake.
+fn transcript(connecting: &Hello, accepting: &Hello) -> blake3::Hash {
+    let encoded = bincode::serialize(&(connecting, accepting)).expect("Failed to serialize a handshake message");
+    blake3::hash(&encoded)
+}
+
+/// Message signed by one side, bound to its role.
+fn signed(role: &[u8], transcript: &blake3::Hash) -> Vec<u8> {
+    [role, transcript.as_bytes()].concat()
+}
+
+fn session(peer: PeerId, ephemeral: &Ephemeral, other: &Hello, transcript: &blake3::Hash, address: SocketAddr) -> Result<Session, NetworkError> {
+    let shared = (MontgomeryPoint(other.ephemeral_key) * ephemeral.secret).to_bytes();
+    if shared == [0; 32] {
+        // The peer sent a point of small order, which does not give a secret.
+        return Err(NetworkError::FailedHandshake(address, "invalid ephemeral key".to_string()));
+    }
+    Ok(Session {
+        peer,
+        key: blake3::derive_key(SESSION_CONTEXT, &[&shared[..], transcript.as_bytes()].concat()),
+        encrypted: ephemeral.hello.encrypt || other.encrypt,
+    })
+}
+
+pub(crate) async fn send<T: AsyncRead + AsyncWrite + Unpin, M: Serialize>(
+    transport: &mut Framed<T, Codec>,
+    message: &M,
+    address: SocketAddr,
+) -> Result<(), NetworkError> {
+    let bytes = bincode::serialize(message).expect("Failed to serialize a handshake message");
+    transport
+        .send(Bytes::from(bytes))
+        .await
+        .map_err(|e| NetworkError::FailedToSendMessage(address, e))
+}
+
+pub(crate) async fn receive<T: AsyncRead + AsyncWrite + Unpin, M: DeserializeOwned>(
+    transport: &mut Framed<T, Codec>,
+    address: SocketAddr,
+) -> Result<M, NetworkError> {
+    match transport.next().await {
+        Some(Ok(bytes)) => bincode::deserialize(&bytes)
+            .map_err(|_| NetworkError::FailedHandshake(address, "malformed handshake message".to_string())),
+        Some(Err(e)) => Err(NetworkError::FailedToReceiveMessage(address, e)),
+        None => Err(NetworkError::FailedHandshake(address, "connection closed".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SecretKey;
+
+    fn keypair(seed: u8) -> Keypair {
+        let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    /// Authenticator of node `id` accepting the nodes whose key is derived from their id, up to `max`.
+    fn authenticator(id: u8, max: u8) -> Authenticator {
+        let keys: Vec<_> = (1..=max).map(|id| keypair(id).public).collect();
+        Authenticator::new(keypair(id), move |key| {
+            keys.iter().position(|k| k == key).map(|index| index as PeerId + 1)
+        })
+    }
+
+    async fn handshake(
+        connecting: Authenticator,
+        accepting: Authenticator,
+    ) -> (Result<Session, NetworkError>, Result<Session, NetworkError>) {
+        let address: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let (left, right) = tokio::io::duplex(1_024);
+        let mut left = Framed::new(left, Codec::new());
+        let mut right = Framed::new(right, Codec::new());
+        // Each side closes its end when done, as the node does.
+        tokio::join!(
+            async move { connecting.connect(&mut left, address).await },
+            async move { accepting.accept(&mut right, address).await },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_handshake() {
+        let (connected, accepted) = handshake(authenticator(1, 4), authenticator(2, 4)).await;
+        let (connected, accepted) = (connected.unwrap(), accepted.unwrap());
+        assert_eq!((connected.peer, accepted.peer), (2, 1));
+        assert_eq!(connected.key, accepted.key);
+        assert!(!connected.encrypted && !accepted.encrypted);
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_loopback() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let accepting = authenticator(2, 4);
+        let echo = tokio::spawn(async move {
+            let (socket, peer) = listener.accept().await.unwrap();
+            let mut transport = Framed::new(socket, Codec::new());
+            let session = accepting.accept(&mut transport, peer).await.unwrap();
+            let message = transport.next().await.unwrap().unwrap();
+            transport.send(message.freeze()).await.unwrap();
+            session
+        });
+
+        // Only the connecting side asks for encryption, which is enough.
+        let socket = tokio::net::TcpStream::connect(address).await.unwrap();
+        let mut transport = Framed::new(socket, Codec::new());
+        let session = authenticator(1, 4).with_encryption(true).connect(&mut transport, address).await.unwrap();
+        assert!(session.encrypted && transport.codec().is_encrypted());
+        transport.send(Bytes::from("vertex")).await.unwrap();
+        assert_eq!(&transport.next().await.unwrap().unwrap()[..], b"vertex");
+        assert_eq!(echo.await.unwrap().key, session.key);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_peer() {
+        // Node 4 is not accepted by node 2, which only knows nodes 1 to 3.
+        let (_, accepted) = handshake(authenticator(4, 4), authenticator(2, 3)).await;
+        assert!(matches!(accepted, Err(NetworkError::UnknownPeer(_))));
+
+        // A node claiming the public key of node 1 cannot sign the challenge.
+        let impostor = Authenticator {
+            keypair: Arc::new(Keypair { secret: keypair(5).secret, public: keypair(1).public }),
+            ..authenticator(1, 3)
+        };
+        let (_, accepted) = handshake(impostor, authenticator(2, 3)).await;
+        assert!(accepted.is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use bytes::{Bytes, BytesMut};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::{fmt, io};
+use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
+
+/// Length-delimited frames, as `LengthDelimitedCodec`, which are encrypted and authenticated once the
+/// handshake of an `Authenticator` sets a cipher. Until then, the frames are left in plaintext.
+#[derive(Debug, Default)]
+pub struct Codec {
+    frames: LengthDelimitedCodec,
+    cipher: Option<Cipher>,
+}
+
+impl Codec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_cipher(&mut self, cipher: Cipher) {
+        self.cipher = Some(cipher);
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+}
+
+impl Encoder<Bytes> for Codec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match &mut self.cipher {
+            Some(cipher) => self.frames.encode(cipher.seal(&item)?, dst),
+            None => self.frames.encode(item, dst),
+        }
+    }
+}
+
+impl Decoder for Codec {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match (self.frames.decode(src)?, &mut self.cipher) {
+            (Some(frame), Some(cipher)) => cipher.open(frame).map(Some),
+            (frame, _) => Ok(frame),
+        }
+    }
+}
+
+/// Key of one direction of a connection, and the number of frames sent that way so far, which
+/// gives the nonce of the next frame.
+struct Direction {
+    aead: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl fmt::Debug for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Direction").field("counter", &self.counter).finish()
+    }
+}
+
+impl Direction {
+    fn new(session_key: &[u8; 32], name: &str) -> Self {
+        let key = blake3::derive_key(&format!("dag-rider 2024 transport {} key", name), session_key);
+        Self { aead: ChaCha20Poly1305::new(&Key::from(key)), counter: 0 }
+    }
+
+    /// Nonce of the current frame, so that frames cannot be replayed or reordered. It never repeats
+    /// under a key, as the connection fails before the counter wraps.
+    fn next_nonce(&mut self) -> Result<Nonce, io::Error> {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.counter.to_le_bytes());
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("no nonce left on the connection"))?;
+        Ok(Nonce::from(nonce))
+    }
+}
+
+/// ChaCha20-Poly1305 cipher of a connection, with a key per direction derived from the session key.
+/// Each side sends with the key of its own direction.
+#[derive(Debug)]
+pub struct Cipher {
+    send: Direction,
+    receive: Direction,
+}
+
+impl Cipher {
+    /// Cipher of the side which opened the connection (`connecting`) or accepted it.
+    pub fn new(session_key: &[u8; 32], connecting: bool) -> Self {
+        let (outgoing, incoming) = (Direction::new(session_key, "outgoing"), Direction::new(session_key, "incoming"));
+        match connecting {
+            true => Self { send: outgoing, receive: incoming },
+            false => Self { send: incoming, receive: outgoing },
+        }
+    }
+
+    fn seal(&mut self, plaintext: &[u8]) -> Result<Bytes, io::Error> {
+        let nonce = self.send.next_nonce()?;
+        self.send
+            .aead
+            .encrypt(&nonce, plaintext)
+            .map(Bytes::from)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "failed to encrypt a frame"))
+    }
+
+    fn open(&mut self, frame: BytesMut) -> Result<BytesMut, io::Error> {
+        let nonce = self.receive.next_nonce()?;
+        self.receive
+            .aead
+            .decrypt(&nonce, &frame[..])
+            .map(|plaintext| BytesMut::from(&plaintext[..]))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid tag on an encrypted frame"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codecs() -> (Codec, Codec) {
+        let (mut connecting, mut accepting) = (Codec::new(), Codec::new());
+        connecting.set_cipher(Cipher::new(&[7; 32], true));
+        accepting.set_cipher(Cipher::new(&[7; 32], false));
+        (connecting, accepting)
+    }
+
+    #[test]
+    fn test_encryption() {
+        let (mut connecting, mut accepting) = codecs();
+        let mut wire = BytesMut::new();
+        for message in ["vertex", "vertex", "block"] {
+            connecting.encode(Bytes::from(message), &mut wire).unwrap();
+        }
+        assert!(!wire.windows(6).any(|window| window == b"vertex"));
+        // Equal messages give different frames, of the length prefix, the message and a 16-byte tag.
+        assert_ne!(wire[4..26], wire[30..52]);
+        for message in ["vertex", "vertex", "block"] {
+            assert_eq!(&accepting.decode(&mut wire).unwrap().unwrap()[..], message.as_bytes());
+        }
+
+        // Replies go the other way with their own keys.
+        accepting.encode(Bytes::from("Ack"), &mut wire).unwrap();
+        assert_eq!(&connecting.decode(&mut wire).unwrap().unwrap()[..], b"Ack");
+    }
+
+    #[test]
+    fn test_tampering() {
+        let (mut connecting, mut accepting) = codecs();
+        let mut wire = BytesMut::new();
+        connecting.encode(Bytes::from("vertex"), &mut wire).unwrap();
+        wire[5] ^= 1;
+        assert!(accepting.decode(&mut wire).is_err());
+
+        // A replayed frame does not match the counter of the receiver anymore.
+        let (mut connecting, mut accepting) = codecs();
+        connecting.encode(Bytes::from("vertex"), &mut wire).unwrap();
+        let replay = wire.clone();
+        assert!(accepting.decode(&mut wire).unwrap().is_some());
+        wire = replay;
+        assert!(accepting.decode(&mut wire).is_err());
+    }
+}
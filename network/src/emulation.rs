@@ -0,0 +1,304 @@
+use crate::error::NetworkError;
+use crate::partition::Partition;
+use rand::rngs::SmallRng;
+use rand::{Rng as _, SeedableRng as _};
+use serde::{Deserialize, Serialize};
+use std::cmp::max;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::sync::watch;
+use tokio::time::{sleep_until, Duration, Instant};
+
+/// TCP never waits less than this before re-transmitting a lost segment.
+const MIN_RETRANSMISSION_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// WAN conditions read from a JSON file. Matrices are indexed by region: the nodes are assigned to the
+/// regions round-robin (node 1 to the first one), so a small matrix between regions can be used with
+/// any number of nodes.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WanConfig {
+    /// One-way latency between two regions (in ms).
+    pub latency: Vec<Vec<u64>>,
+    /// Maximum deviation from the latency between two regions (in ms), no jitter if empty.
+    #[serde(default)]
+    pub jitter: Vec<Vec<u64>>,
+    /// Probability that a message is lost (and re-transmitted, for reliable connections).
+    #[serde(default)]
+    pub loss: f64,
+    /// Upload and download bandwidth of every node (in bytes per second), unlimited if missing.
+    #[serde(default)]
+    pub bandwidth: Option<u64>,
+}
+
+impl WanConfig {
+    pub fn check(&self) -> Result<(), NetworkError> {
+        let regions = self.latency.len();
+        if regions == 0 || self.latency.iter().any(|row| row.len() != regions) {
+            return Err(NetworkError::InvalidWanConfig("latency must be a non-empty square matrix".to_string()));
+        }
+        if !self.jitter.is_empty() && (self.jitter.len() != regions || self.jitter.iter().any(|row| row.len() != regions)) {
+            return Err(NetworkError::InvalidWanConfig("jitter must have the same size as latency".to_string()));
+        }
+        if !(0.0..1.0).contains(&self.loss) {
+            return Err(NetworkError::InvalidWanConfig("loss must be in [0, 1)".to_string()));
+        }
+        if self.bandwidth == Some(0) {
+            return Err(NetworkError::InvalidWanConfig("bandwidth must be positive".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Conditions of the link between two nodes.
+    pub fn link(&self, from: u32, to: u32) -> LinkConditions {
+        let region = |id: u32| (id as usize).saturating_sub(1) % self.latency.len();
+        let (from, to) = (region(from), region(to));
+        LinkConditions {
+            latency: Duration::from_millis(self.latency[from][to]),
+            jitter: Duration::from_millis(self.jitter.get(from).map_or(0, |row| row[to])),
+            loss: self.loss,
+        }
+    }
+
+    /// Emulator of a node, whose peers are given by id and address.
+    pub fn emulator(&self, node: u32, peers: impl IntoIterator<Item = (u32, SocketAddr)>) -> Emulator {
+        let links = peers
+            .into_iter()
+            .map(|(peer, address)| (address, (peer, self.link(node, peer))))
+            .collect();
+        Emulator::new(node, links, self.bandwidth)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkConditions {
+    pub latency: Duration,
+    pub jitter: Duration,
+    pub loss: f64,
+}
+
+/// When a message sent through the emulator reaches its destination.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transmission {
+    /// The destination is not emulated, send the message right away.
+    Immediate,
+    At(Instant),
+    Lost,
+}
+
+/// Emulates WAN conditions on the traffic of a node: latency, jitter and loss on the links towards
+/// its peers, partitions cutting some of these links, and a cap on its upload and download bandwidth.
+/// Traffic to other addresses (e.g. the node itself or clients) is left untouched, as is everything
+/// with the default emulator.
+#[derive(Clone, Default)]
+pub struct Emulator {
+    inner: Option<Arc<Inner>>,
+}
+
+struct Inner {
+    node: u32,
+    /// The peer at each emulated address and the conditions of the link towards it.
+    links: HashMap<SocketAddr, (u32, LinkConditions)>,
+    bandwidth: Option<u64>,
+    partition: watch::Sender<Option<Partition>>,
+    state: Mutex<State>,
+}
+
+struct State {
+    rng: SmallRng,
+    /// When the messages queued so far are done uploading.
+    upload_free_at: Instant,
+    /// When the messages received so far are done downloading.
+    download_free_at: Instant,
+    /// Arrival of the last message sent on each link, which later messages cannot overtake.
+    last_arrivals: HashMap<SocketAddr, Instant>,
+}
+
+impl Emulator {
+    pub fn new(node: u32, links: HashMap<SocketAddr, (u32, LinkConditions)>, bandwidth: Option<u64>) -> Self {
+        let now = Instant::now();
+        Self {
+            inner: Some(Arc::new(Inner {
+                node,
+                links,
+                bandwidth,
+                partition: watch::channel(None).0,
+                state: Mutex::new(State {
+                    rng: SmallRng::from_entropy(),
+                    upload_free_at: now,
+                    download_free_at: now,
+                    last_arrivals: HashMap::new(),
+                }),
+            })),
+        }
+    }
+
+    /// Cut the links between the groups of the partition, or restore all of them.
+    pub fn set_partition(&self, partition: Option<Partition>) {
+        if let Some(inner) = &self.inner {
+            inner.partition.send_replace(partition);
+        }
+    }
+
+    /// Whether the link towards `address` is cut by the current partition.
+    fn is_cut(&self, address: SocketAddr) -> bool {
+        match (&self.inner, self.inner.as_ref().and_then(|inner| inner.links.get(&address))) {
+            (Some(inner), Some((peer, _))) => match &*inner.partition.borrow() {
+                Some(partition) => partition.separates(inner.node, *peer),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Wait until the link towards `address` is not cut by a partition.
+    async fn wait_for_link(&self, address: SocketAddr) {
+        if let Some(inner) = &self.inner {
+            let mut partition = inner.partition.subscribe();
+            while self.is_cut(address) {
+                let _ = partition.changed().await;
+            }
+        }
+    }
+
+    /// Decide when a message of `size` bytes sent now to `address` arrives. Lost messages of reliable
+    /// connections are re-transmitted after a timeout, like TCP does, instead of being dropped. Messages
+    /// of reliable connections cut by a partition wait for the partition to heal, the others are lost.
+    pub fn transmit(&self, address: SocketAddr, size: usize, reliable: bool) -> Transmission {
+        let inner = match &self.inner {
+            Some(inner) => inner,
+            None => return Transmission::Immediate,
+        };
+        let (_, link) = match inner.links.get(&address) {
+            Some(link) => link,
+            None => return Transmission::Immediate,
+        };
+        if !reliable && self.is_cut(address) {
+            return Transmission::Lost;
+        }
+        let mut state = inner.state.lock().unwrap();
+
+        let now = Instant::now();
+        let uploaded = max(now, state.upload_free_at) + Self::transfer_time(inner.bandwidth, size);
+        state.upload_free_at = uploaded;
+
+        let mut delay = link.latency;
+        if link.jitter > Duration::ZERO {
+            let low = link.latency.saturating_sub(link.jitter);
+            delay = state.rng.gen_range(low..=link.latency + link.jitter);
+        }
+        while link.loss > 0.0 && state.rng.gen_bool(link.loss) {
+            if !reliable {
+                return Transmission::Lost;
+            }
+            delay += max(MIN_RETRANSMISSION_TIMEOUT, link.latency * 2);
+        }
+
+        let last_arrival = state.last_arrivals.entry(address).or_insert(now);
+        let arrival = max(uploaded + delay, *last_arrival);
+        *last_arrival = arrival;
+        Transmission::At(arrival)
+    }
+
+    /// Time at which a message of `size` bytes received now is done downloading, if it is limited.
+    pub fn receive(&self, size: usize) -> Option<Instant> {
+        let inner = self.inner.as_ref()?;
+        inner.bandwidth?;
+        let mut state = inner.state.lock().unwrap();
+        let downloaded = max(Instant::now(), state.download_free_at) + Self::transfer_time(inner.bandwidth, size);
+        state.download_free_at = downloaded;
+        Some(downloaded)
+    }
+
+    fn transfer_time(bandwidth: Option<u64>, size: usize) -> Duration {
+        match bandwidth {
+            Some(bandwidth) => Duration::from_secs_f64(size as f64 / bandwidth as f64),
+            None => Duration::ZERO,
+        }
+    }
+}
+
+/// Spawn a task delaying the messages of the link towards `address`: each one is forwarded to `forward`
+/// at its arrival time, or once the link is not cut by a partition anymore. Arrival times of a link
+/// never decrease, so messages are forwarded in order.
+pub(crate) fn spawn_link<T: Send + 'static>(
+    forward: Sender<T>,
+    emulator: Emulator,
+    address: SocketAddr,
+) -> Sender<(Instant, T)> {
+    let (tx, mut rx) = channel::<(Instant, T)>(1_000);
+    tokio::spawn(async move {
+        while let Some((arrival, message)) = rx.recv().await {
+            sleep_until(arrival).await;
+            emulator.wait_for_link(address).await;
+            if forward.send(message).await.is_err() {
+                return;
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emulator(latency: u64, loss: f64, bandwidth: Option<u64>) -> (Emulator, SocketAddr) {
+        let address: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let config = WanConfig {
+            latency: vec![vec![0, latency], vec![latency, 0]],
+            jitter: vec![],
+            loss,
+            bandwidth,
+        };
+        (config.emulator(1, vec![(2, address)]), address)
+    }
+
+    #[test]
+    fn test_latency_and_bandwidth() {
+        let (emulator, address) = emulator(100, 0.0, Some(1_000));
+        let start = Instant::now();
+        let first = match emulator.transmit(address, 500, true) {
+            Transmission::At(arrival) => arrival,
+            other => panic!("Unexpected transmission {:?}", other),
+        };
+        let second = match emulator.transmit(address, 500, true) {
+            Transmission::At(arrival) => arrival,
+            other => panic!("Unexpected transmission {:?}", other),
+        };
+        assert!(first >= start + Duration::from_millis(600));
+        assert!(second >= first + Duration::from_millis(500));
+        assert_eq!(emulator.transmit("127.0.0.1:9001".parse().unwrap(), 500, true), Transmission::Immediate);
+    }
+
+    #[test]
+    fn test_loss() {
+        let (emulator, address) = emulator(100, 0.5, None);
+        let transmissions: Vec<_> = (0..100).map(|_| emulator.transmit(address, 10, false)).collect();
+        assert!(transmissions.contains(&Transmission::Lost));
+        assert!((0..100).all(|_| emulator.transmit(address, 10, true) != Transmission::Lost));
+    }
+
+    #[test]
+    fn test_partition() {
+        let (emulator, address) = emulator(100, 0.0, None);
+        emulator.set_partition(Some("1|2".parse().unwrap()));
+        assert!(emulator.is_cut(address));
+        assert_eq!(emulator.transmit(address, 10, false), Transmission::Lost);
+        assert_ne!(emulator.transmit(address, 10, true), Transmission::Lost);
+        emulator.set_partition(Some("1,2|3".parse().unwrap()));
+        assert!(!emulator.is_cut(address));
+    }
+
+    #[test]
+    fn test_check() {
+        let mut config = WanConfig { latency: vec![vec![0, 10], vec![10, 0]], ..WanConfig::default() };
+        assert!(config.check().is_ok());
+        config.jitter = vec![vec![1]];
+        assert!(config.check().is_err());
+        config.jitter = vec![];
+        config.loss = 1.0;
+        assert!(config.check().is_err());
+    }
+}
@@ -21,4 +21,25 @@ pub enum NetworkError {
 
     #[error("Receive unexpected ACK from {0}")]
     UnexpectedAck(SocketAddr),
+
+    #[error("Failed handshake with {0}: {1}")]
+    FailedHandshake(SocketAddr, String),
+
+    #[error("Peer {0} does not speak the protocol of the nodes")]
+    InvalidPreamble(SocketAddr),
+
+    #[error("Peer {0} speaks version {1} of the protocol, this node version {2}: upgrade the older one")]
+    VersionMismatch(SocketAddr, u16, u16),
+
+    #[error("Rejected unknown peer {0}")]
+    UnknownPeer(SocketAddr),
+
+    #[error("Invalid WAN emulation config: {0}")]
+    InvalidWanConfig(String),
+
+    #[error("Invalid partition {0}, expected groups of node ids like 1,2|3,4")]
+    InvalidPartition(String),
+
+    #[error("Invalid partition scenario: {0}")]
+    InvalidScenario(String),
 }
@@ -1,8 +1,18 @@
+pub mod auth;
+pub mod codec;
+pub mod emulation;
 pub mod error;
+pub mod partition;
 pub mod receiver;
 pub mod reliable_sender;
 pub mod simple_sender;
+pub mod version;
 
-pub use crate::receiver::{MessageHandler, Receiver, Writer};
+pub use crate::auth::{Authenticator, PeerId, Session};
+pub use crate::codec::Codec;
+pub use crate::emulation::{Emulator, LinkConditions, WanConfig};
+pub use crate::partition::{Partition, Scenario, ScenarioEvent};
+pub use crate::receiver::{MessageHandler, Receiver, Replier, Writer};
 pub use crate::reliable_sender::{CancelHandler, ReliableSender};
 pub use crate::simple_sender::SimpleSender;
+pub use crate::version::PROTOCOL_VERSION;
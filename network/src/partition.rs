@@ -0,0 +1,155 @@
+use crate::error::NetworkError;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// Groups of nodes which can only talk within their group, written `1,2|3,4`. Nodes missing from
+/// every group are isolated.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Partition {
+    groups: Vec<BTreeSet<u32>>,
+}
+
+impl Partition {
+    /// Whether messages between the two nodes are cut by the partition.
+    pub fn separates(&self, a: u32, b: u32) -> bool {
+        if a == b {
+            return false;
+        }
+        match self.groups.iter().find(|group| group.contains(&a)) {
+            Some(group) => !group.contains(&b),
+            None => true,
+        }
+    }
+}
+
+impl FromStr for Partition {
+    type Err = NetworkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || NetworkError::InvalidPartition(s.to_string());
+        let groups = s
+            .split('|')
+            .map(|group| {
+                group
+                    .split(',')
+                    .map(|id| id.trim().parse::<u32>().map_err(|_| invalid()))
+                    .collect::<Result<BTreeSet<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let nodes = groups.iter().map(BTreeSet::len).sum::<usize>();
+        if groups.iter().flatten().collect::<BTreeSet<_>>().len() != nodes {
+            // A node cannot be in two groups.
+            return Err(invalid());
+        }
+        Ok(Self { groups })
+    }
+}
+
+impl TryFrom<String> for Partition {
+    type Error = NetworkError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Partition> for String {
+    fn from(partition: Partition) -> Self {
+        partition.to_string()
+    }
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let groups: Vec<String> = self
+            .groups
+            .iter()
+            .map(|group| group.iter().map(u32::to_string).collect::<Vec<_>>().join(","))
+            .collect();
+        write!(f, "{}", groups.join("|"))
+    }
+}
+
+/// Partitions switched on and off during a run, read from a JSON file.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scenario {
+    /// Partitions which the events can refer to by name.
+    #[serde(default)]
+    pub partitions: HashMap<String, Partition>,
+    pub events: Vec<ScenarioEvent>,
+}
+
+/// Switch to a partition, or heal the network if there is none, at a round or at a time.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScenarioEvent {
+    /// Round reached by the node.
+    #[serde(default)]
+    pub at_round: Option<u64>,
+    /// Time since the node started (in ms).
+    #[serde(default)]
+    pub at_ms: Option<u64>,
+    /// Name of a partition of the scenario, or a partition like `1,2|3,4`.
+    #[serde(default)]
+    pub partition: Option<String>,
+}
+
+impl Scenario {
+    pub fn check(&self) -> Result<(), NetworkError> {
+        for event in &self.events {
+            if event.at_round.is_some() == event.at_ms.is_some() {
+                return Err(NetworkError::InvalidScenario("events need either at_round or at_ms".to_string()));
+            }
+            self.partition(event)?;
+        }
+        Ok(())
+    }
+
+    /// The partition the network is in after the event.
+    pub fn partition(&self, event: &ScenarioEvent) -> Result<Option<Partition>, NetworkError> {
+        match &event.partition {
+            Some(name) => match self.partitions.get(name) {
+                Some(partition) => Ok(Some(partition.clone())),
+                None => name.parse().map(Some),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partition() {
+        let partition: Partition = "1,2 | 3,4".parse().unwrap();
+        assert!(!partition.separates(1, 2));
+        assert!(partition.separates(2, 3));
+        assert!(partition.separates(5, 1));
+        assert_eq!(partition.to_string(), "1,2|3,4");
+        assert!("1,2|2,3".parse::<Partition>().is_err());
+        assert!("1,a".parse::<Partition>().is_err());
+    }
+
+    #[test]
+    fn test_scenario() {
+        let scenario: Scenario = serde_json::from_str(
+            r#"{
+                "partitions": { "split": "1,2|3,4" },
+                "events": [
+                    { "at_round": 10, "partition": "split" },
+                    { "at_ms": 5000, "partition": "1|2,3,4" },
+                    { "at_round": 30 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert!(scenario.check().is_ok());
+        assert_eq!(scenario.partition(&scenario.events[0]).unwrap(), Some("1,2|3,4".parse().unwrap()));
+        assert_eq!(scenario.partition(&scenario.events[2]).unwrap(), None);
+    }
+}
@@ -1,24 +1,49 @@
+use crate::auth::{Authenticator, PeerId};
+use crate::codec::Codec;
+use crate::emulation::Emulator;
 use crate::error::NetworkError;
+use crate::version;
 use async_trait::async_trait;
 use bytes::Bytes;
+use futures::sink::SinkExt as _;
 use futures::stream::SplitSink;
 use futures::stream::StreamExt as _;
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
 use std::error::Error;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::sleep_until;
+use tokio_util::codec::Framed;
 
 /// Convenient alias for the writer end of the TCP channel.
-pub type Writer = SplitSink<Framed<TcpStream, LengthDelimitedCodec>, Bytes>;
+pub type Writer = SplitSink<Framed<TcpStream, Codec>, Bytes>;
+
+/// Sends messages back on a connection at any time, like the notifications following a request,
+/// until the connection closes. The replies are written between the incoming messages, so a peer
+/// which does not read them holds up its own connection.
+pub type Replier = Sender<Bytes>;
+
+/// Number of replies waiting to be written on a connection.
+const REPLY_CAPACITY: usize = 1_000;
 
 #[async_trait]
 pub trait MessageHandler: Clone + Send + Sync + 'static {
     /// Defines how to handle an incoming message. A typical usage is to define a `MessageHandler` with a
     /// number of `Sender<T>` channels. Then implement `dispatch` to deserialize incoming messages and
     /// forward them through the appropriate delivery channel. Then `writer` can be used to send back
-    /// responses or acknowledgements to the sender machine (see unit tests for examples).
-    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>>;
+    /// responses or acknowledgements to the sender machine (see unit tests for examples), and
+    /// `replier` to send later ones. `peer` is the sender authenticated by the handshake, `None` if
+    /// the receiver does not authenticate its peers.
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        message: Bytes,
+        peer: Option<PeerId>,
+        replier: &Replier,
+    ) -> Result<(), Box<dyn Error>>;
 }
 
 /// For each incoming request, we spawn a new runner responsible to receive messages and forward them
@@ -28,15 +53,41 @@ pub struct Receiver<Handler: MessageHandler> {
     address: SocketAddr,
     /// Struct responsible to define how to handle received messages.
     handler: Handler,
-    /// Whether to simulate a network failure by dropping packets.
-    should_drop_packets: bool,
+    /// Whether to simulate a network failure by dropping packets. It can change while running.
+    should_drop_packets: Arc<AtomicBool>,
+    /// Emulated WAN, limiting the download bandwidth.
+    emulator: Emulator,
+    /// Authenticates the peers of every connection, if set.
+    authenticator: Option<Authenticator>,
 }
 
 impl<Handler: MessageHandler> Receiver<Handler> {
     /// Spawn a new network receiver handling connections from any incoming peer.
-    pub fn spawn(address: SocketAddr, handler: Handler, should_drop_packets: bool) {
+    pub fn spawn(address: SocketAddr, handler: Handler, should_drop_packets: Arc<AtomicBool>) {
+        Self::spawn_with_emulator(address, handler, should_drop_packets, Emulator::default());
+    }
+
+    /// Spawn a new network receiver whose incoming messages go through the given WAN emulator.
+    pub fn spawn_with_emulator(
+        address: SocketAddr,
+        handler: Handler,
+        should_drop_packets: Arc<AtomicBool>,
+        emulator: Emulator,
+    ) {
+        Self::spawn_authenticated(address, handler, should_drop_packets, emulator, None);
+    }
+
+    /// Spawn a new network receiver rejecting the connections of peers which fail the handshake of
+    /// `authenticator`, if it is set.
+    pub fn spawn_authenticated(
+        address: SocketAddr,
+        handler: Handler,
+        should_drop_packets: Arc<AtomicBool>,
+        emulator: Emulator,
+        authenticator: Option<Authenticator>,
+    ) {
         tokio::spawn(async move {
-            Self { address, handler, should_drop_packets }.run().await;
+            Self { address, handler, should_drop_packets, emulator, authenticator }.run().await;
         });
     }
 
@@ -56,24 +107,85 @@ impl<Handler: MessageHandler> Receiver<Handler> {
                 }
             };
             info!("Incoming connection established with {}", peer);
-            Self::spawn_runner(socket, peer, self.handler.clone(), self.should_drop_packets.clone()).await;
+            Self::spawn_runner(
+                socket,
+                peer,
+                self.handler.clone(),
+                self.should_drop_packets.clone(),
+                self.emulator.clone(),
+                self.authenticator.clone(),
+            )
+            .await;
         }
     }
 
     /// Spawn a new runner to handle a specific TCP connection. It receives messages and process them
     /// using the provided handler.
-    async fn spawn_runner(socket: TcpStream, peer: SocketAddr, handler: Handler, should_drop_packets: bool) {
+    async fn spawn_runner(
+        socket: TcpStream,
+        peer: SocketAddr,
+        handler: Handler,
+        should_drop_packets: Arc<AtomicBool>,
+        emulator: Emulator,
+        authenticator: Option<Authenticator>,
+    ) {
         tokio::spawn(async move {
-            let transport = Framed::new(socket, LengthDelimitedCodec::new());
+            let mut transport = Framed::new(socket, Codec::new());
+            let authenticate = match version::answer(&mut transport, peer).await {
+                Ok(authenticate) => authenticate,
+                Err(e @ NetworkError::VersionMismatch(..)) => {
+                    error!("{}", e);
+                    return;
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    return;
+                }
+            };
+            // Peers which do not authenticate, like clients, are anonymous.
+            let peer_id = match (&authenticator, authenticate) {
+                (Some(authenticator), true) => match authenticator.accept(&mut transport, peer).await {
+                    Ok(session) => {
+                        debug!("Authenticated peer {} at {}", session.peer, peer);
+                        Some(session.peer)
+                    }
+                    Err(e) => {
+                        warn!("{}", e);
+                        return;
+                    }
+                },
+                (None, true) => {
+                    warn!("{}", NetworkError::FailedHandshake(peer, "this node does not authenticate its peers".to_string()));
+                    return;
+                }
+                (_, false) => None,
+            };
             let (mut writer, mut reader) = transport.split();
-            while let Some(frame) = reader.next().await {
+            let (replier, mut replies) = channel(REPLY_CAPACITY);
+            loop {
+                let frame = tokio::select! {
+                    frame = reader.next() => match frame {
+                        Some(frame) => frame,
+                        None => break,
+                    },
+                    Some(reply) = replies.recv() => {
+                        if let Err(e) = writer.send(reply).await {
+                            warn!("{}", NetworkError::FailedToSendMessage(peer, e));
+                            return;
+                        }
+                        continue;
+                    }
+                };
                 match frame.map_err(|e| NetworkError::FailedToReceiveMessage(peer, e)) {
                     Ok(message) => {
-                        if should_drop_packets {
+                        if should_drop_packets.load(Ordering::Relaxed) {
                             // drop packet
                             continue;
                         }
-                        if let Err(e) = handler.dispatch(&mut writer, message.freeze()).await {
+                        if let Some(downloaded) = emulator.receive(message.len()) {
+                            sleep_until(downloaded).await;
+                        }
+                        if let Err(e) = handler.dispatch(&mut writer, message.freeze(), peer_id, &replier).await {
                             warn!("{}", e);
                             return;
                         }
@@ -1,8 +1,12 @@
+use crate::auth::Authenticator;
+use crate::codec::Codec;
+use crate::emulation::{self, Emulator, Transmission};
 use crate::error::NetworkError;
+use crate::version;
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
-use log::{info, warn};
+use log::{error, info, warn};
 use rand::prelude::SliceRandom as _;
 use rand::rngs::SmallRng;
 use rand::SeedableRng as _;
@@ -13,8 +17,8 @@ use std::net::SocketAddr;
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::oneshot;
-use tokio::time::{sleep, Duration};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::codec::Framed;
 
 /// Convenient alias for cancel handlers returned to the caller task.
 pub type CancelHandler = oneshot::Receiver<Bytes>;
@@ -26,8 +30,13 @@ pub type CancelHandler = oneshot::Receiver<Bytes>;
 pub struct ReliableSender {
     /// A map holding the channels to our connections.
     connections: HashMap<SocketAddr, Sender<InnerMessage>>,
+    /// The channels delaying the messages of our connections, when emulating a WAN.
+    links: HashMap<SocketAddr, Sender<(Instant, InnerMessage)>>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    emulator: Emulator,
+    /// Authenticates this node to the peers of every connection, if set.
+    authenticator: Option<Authenticator>,
 }
 
 impl std::default::Default for ReliableSender {
@@ -38,31 +47,59 @@ impl std::default::Default for ReliableSender {
 
 impl ReliableSender {
     pub fn new() -> Self {
+        Self::with_emulator(Emulator::default())
+    }
+
+    /// Create a sender whose messages go through the given WAN emulator.
+    pub fn with_emulator(emulator: Emulator) -> Self {
         Self {
             connections: HashMap::new(),
+            links: HashMap::new(),
             rng: SmallRng::from_entropy(),
+            emulator,
+            authenticator: None,
         }
     }
 
+    /// Run the handshake of `authenticator`, if set, on every new connection.
+    pub fn with_authenticator(mut self, authenticator: Option<Authenticator>) -> Self {
+        self.authenticator = authenticator;
+        self
+    }
+
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<InnerMessage> {
+    fn spawn_connection(address: SocketAddr, authenticator: Option<Authenticator>) -> Sender<InnerMessage> {
         let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
+        Connection::spawn(address, authenticator, rx);
         tx
     }
 
     /// Reliably send a message to a specific address.
     pub async fn send(&mut self, address: SocketAddr, data: Bytes) -> CancelHandler {
         let (sender, receiver) = oneshot::channel();
-        self.connections
-            .entry(address)
-            .or_insert_with(|| Self::spawn_connection(address))
-            .send(InnerMessage {
-                data,
-                cancel_handler: sender,
-            })
-            .await
-            .expect("Failed to send internal message");
+        let message = InnerMessage {
+            data,
+            cancel_handler: sender,
+        };
+        match self.emulator.transmit(address, message.data.len(), /* reliable */ true) {
+            Transmission::At(arrival) => self
+                .links
+                .entry(address)
+                .or_insert_with(|| {
+                    let connection = Self::spawn_connection(address, self.authenticator.clone());
+                    emulation::spawn_link(connection, self.emulator.clone(), address)
+                })
+                .send((arrival, message))
+                .await
+                .expect("Failed to send internal message"),
+            _ => self
+                .connections
+                .entry(address)
+                .or_insert_with(|| Self::spawn_connection(address, self.authenticator.clone()))
+                .send(message)
+                .await
+                .expect("Failed to send internal message"),
+        }
         receiver
     }
 
@@ -109,6 +146,7 @@ struct InnerMessage {
 struct Connection {
     /// The destination address.
     address: SocketAddr,
+    authenticator: Option<Authenticator>,
     /// Channel from which the connection receives its commands.
     receiver: Receiver<InnerMessage>,
     /// The initial delay to wait before re-attempting a connection (in ms).
@@ -118,10 +156,11 @@ struct Connection {
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<InnerMessage>) {
+    fn spawn(address: SocketAddr, authenticator: Option<Authenticator>, receiver: Receiver<InnerMessage>) {
         tokio::spawn(async move {
             Self {
                 address,
+                authenticator,
                 receiver,
                 retry_delay: 200,
                 buffer: VecDeque::new(),
@@ -136,8 +175,8 @@ impl Connection {
         let mut delay = self.retry_delay;
         let mut retry = 0;
         loop {
-            match TcpStream::connect(self.address).await {
-                Ok(stream) => {
+            match self.connect(retry).await {
+                Ok(transport) => {
                     info!("Outgoing connection established with {}", self.address);
 
                     // Reset the delay.
@@ -146,11 +185,14 @@ impl Connection {
 
                     // Try to transmit all messages in the buffer and keep transmitting incoming messages.
                     // The following function only returns if there is an error.
-                    let error = self.keep_alive(stream).await;
+                    let error = self.keep_alive(transport).await;
                     warn!("{}", error);
                 }
                 Err(e) => {
-                    warn!("{}", NetworkError::FailedToConnect(self.address, retry, e));
+                    match e {
+                        NetworkError::VersionMismatch(..) => error!("{}", e),
+                        _ => warn!("{}", e),
+                    }
                     let timer = sleep(Duration::from_millis(delay));
                     tokio::pin!(timer);
 
@@ -176,13 +218,27 @@ impl Connection {
         }
     }
 
+    /// Connect to the peer, check that it speaks our version of the protocol and run the handshake, if
+    /// the connection is authenticated.
+    async fn connect(&self, retry: u16) -> Result<Framed<TcpStream, Codec>, NetworkError> {
+        let stream = TcpStream::connect(self.address)
+            .await
+            .map_err(|e| NetworkError::FailedToConnect(self.address, retry, e))?;
+        let mut transport = Framed::new(stream, Codec::new());
+        version::announce(&mut transport, self.address, self.authenticator.is_some()).await?;
+        if let Some(authenticator) = &self.authenticator {
+            authenticator.connect(&mut transport, self.address).await?;
+        }
+        Ok(transport)
+    }
+
     /// Transmit messages once we have established a connection.
-    async fn keep_alive(&mut self, stream: TcpStream) -> NetworkError {
+    async fn keep_alive(&mut self, transport: Framed<TcpStream, Codec>) -> NetworkError {
         // This buffer keeps all messages and handlers that we have successfully transmitted but for
         // which we are still waiting to receive an ACK.
         let mut pending_replies = VecDeque::new();
 
-        let (mut writer, mut reader) = Framed::new(stream, LengthDelimitedCodec::new()).split();
+        let (mut writer, mut reader) = transport.split();
         let error = 'connection: loop {
             // Try to send all messages of the buffer.
             while let Some((data, handler)) = self.buffer.pop_front() {
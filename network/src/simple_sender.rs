@@ -1,8 +1,12 @@
+use crate::auth::Authenticator;
+use crate::codec::Codec;
+use crate::emulation::{self, Emulator, Transmission};
 use crate::error::NetworkError;
+use crate::version;
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
-use log::{info, warn};
+use log::{error, info, warn};
 use rand::prelude::SliceRandom as _;
 use rand::rngs::SmallRng;
 use rand::SeedableRng as _;
@@ -10,15 +14,21 @@ use std::collections::HashMap;
 use std::net::SocketAddr;
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio::time::Instant;
+use tokio_util::codec::Framed;
 
 /// We keep alive one TCP connection per peer, each connection is handled by a separate task (called `Connection`).
 /// We communicate with our 'connections' through a dedicated channel kept by the HashMap called `connections`.
 pub struct SimpleSender {
     /// A map holding the channels to our connections.
     connections: HashMap<SocketAddr, Sender<Bytes>>,
+    /// The channels delaying the messages of our connections, when emulating a WAN.
+    links: HashMap<SocketAddr, Sender<(Instant, Bytes)>>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    emulator: Emulator,
+    /// Authenticates this node to the peers of every connection, if set.
+    authenticator: Option<Authenticator>,
 }
 
 impl std::default::Default for SimpleSender {
@@ -29,22 +39,55 @@ impl std::default::Default for SimpleSender {
 
 impl SimpleSender {
     pub fn new() -> Self {
+        Self::with_emulator(Emulator::default())
+    }
+
+    /// Create a sender whose messages go through the given WAN emulator.
+    pub fn with_emulator(emulator: Emulator) -> Self {
         Self {
             connections: HashMap::new(),
+            links: HashMap::new(),
             rng: SmallRng::from_entropy(),
+            emulator,
+            authenticator: None,
         }
     }
 
+    /// Run the handshake of `authenticator`, if set, on every new connection.
+    pub fn with_authenticator(mut self, authenticator: Option<Authenticator>) -> Self {
+        self.authenticator = authenticator;
+        self
+    }
+
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<Bytes> {
+    fn spawn_connection(address: SocketAddr, authenticator: Option<Authenticator>) -> Sender<Bytes> {
         let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
+        Connection::spawn(address, authenticator, rx);
         tx
     }
 
     /// Try (best-effort) to send a message to a specific address.
     /// This is useful to answer sync requests.
     pub async fn send(&mut self, address: SocketAddr, data: Bytes) {
+        match self.emulator.transmit(address, data.len(), /* reliable */ false) {
+            Transmission::Immediate => (),
+            Transmission::Lost => return,
+            Transmission::At(arrival) => {
+                // Links stop when their connection fails, like connections themselves.
+                if let Some(link) = self.links.get(&address) {
+                    if link.send((arrival, data.clone())).await.is_ok() {
+                        return;
+                    }
+                }
+                let connection = Self::spawn_connection(address, self.authenticator.clone());
+                let link = emulation::spawn_link(connection, self.emulator.clone(), address);
+                if link.send((arrival, data)).await.is_ok() {
+                    self.links.insert(address, link);
+                }
+                return;
+            }
+        }
+
         // Try to re-use an existing connection if possible.
         if let Some(tx) = self.connections.get(&address) {
             if tx.send(data.clone()).await.is_ok() {
@@ -53,7 +96,7 @@ impl SimpleSender {
         }
 
         // Otherwise make a new connection.
-        let tx = Self::spawn_connection(address);
+        let tx = Self::spawn_connection(address, self.authenticator.clone());
         if tx.send(data).await.is_ok() {
             self.connections.insert(address, tx);
         }
@@ -84,22 +127,23 @@ impl SimpleSender {
 struct Connection {
     /// The destination address.
     address: SocketAddr,
+    authenticator: Option<Authenticator>,
     /// Channel from which the connection receives its commands.
     receiver: Receiver<Bytes>,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<Bytes>) {
+    fn spawn(address: SocketAddr, authenticator: Option<Authenticator>, receiver: Receiver<Bytes>) {
         tokio::spawn(async move {
-            Self { address, receiver }.run().await;
+            Self { address, authenticator, receiver }.run().await;
         });
     }
 
     /// Main loop trying to connect to the peer and transmit messages.
     async fn run(&mut self) {
         // Try to connect to the peer.
-        let (mut writer, mut reader) = match TcpStream::connect(self.address).await {
-            Ok(stream) => Framed::new(stream, LengthDelimitedCodec::new()).split(),
+        let mut transport = match TcpStream::connect(self.address).await {
+            Ok(stream) => Framed::new(stream, Codec::new()),
             Err(e) => {
                 warn!(
                     "{}",
@@ -108,6 +152,20 @@ impl Connection {
                 return;
             }
         };
+        if let Err(e) = version::announce(&mut transport, self.address, self.authenticator.is_some()).await {
+            match e {
+                NetworkError::VersionMismatch(..) => error!("{}", e),
+                _ => warn!("{}", e),
+            }
+            return;
+        }
+        if let Some(authenticator) = &self.authenticator {
+            if let Err(e) = authenticator.connect(&mut transport, self.address).await {
+                warn!("{}", e);
+                return;
+            }
+        }
+        let (mut writer, mut reader) = transport.split();
         info!("Outgoing connection established with {}", self.address);
 
         // Transmit messages once we have established a connection.
@@ -0,0 +1,135 @@
+use crate::auth::{receive, send, Authenticator};
+use crate::codec::Codec;
+use crate::error::NetworkError;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+use tokio_util::codec::Framed;
+
+/// Version of the wire protocol: this preamble, the handshake and the messages of the nodes. Bump it
+/// whenever one of them changes, so that nodes running another version refuse to talk instead of
+/// misreading each other.
+pub const PROTOCOL_VERSION: u16 = 9;
+
+/// First bytes of every connection, telling apart peers which do not speak the protocol at all.
+const MAGIC: [u8; 4] = *b"dagr";
+
+/// A peer taking longer than this to send its preamble is dropped.
+const PREAMBLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// First frame sent on a connection by each side.
+#[derive(Serialize, Deserialize)]
+struct Preamble {
+    magic: [u8; 4],
+    version: u16,
+    /// Whether the connecting side runs the handshake of an `Authenticator` next.
+    authenticate: bool,
+}
+
+impl Preamble {
+    fn new(authenticate: bool) -> Self {
+        Self { magic: MAGIC, version: PROTOCOL_VERSION, authenticate }
+    }
+
+    fn check(&self, address: SocketAddr) -> Result<(), NetworkError> {
+        if self.magic != MAGIC {
+            return Err(NetworkError::InvalidPreamble(address));
+        }
+        if self.version != PROTOCOL_VERSION {
+            return Err(NetworkError::VersionMismatch(address, self.version, PROTOCOL_VERSION));
+        }
+        Ok(())
+    }
+}
+
+/// Open a new outgoing connection to `address`, saying whether the handshake follows. Fails if the
+/// peer speaks another version of the protocol.
+pub async fn announce<T: AsyncRead + AsyncWrite + Unpin>(
+    transport: &mut Framed<T, Codec>,
+    address: SocketAddr,
+    authenticate: bool,
+) -> Result<(), NetworkError> {
+    timeout(PREAMBLE_TIMEOUT, async {
+        send(transport, &Preamble::new(authenticate), address).await?;
+        receive::<_, Preamble>(transport, address).await.map_err(|_| NetworkError::InvalidPreamble(address))?.check(address)
+    })
+    .await
+    .unwrap_or(Err(NetworkError::InvalidPreamble(address)))
+}
+
+/// Connect to a node as a client, which does not authenticate, ready to send it messages.
+pub async fn connect(address: SocketAddr) -> Result<Framed<TcpStream, Codec>, NetworkError> {
+    let stream = TcpStream::connect(address)
+        .await
+        .map_err(|e| NetworkError::FailedToConnect(address, /* retry */ 0, e))?;
+    let mut transport = Framed::new(stream, Codec::new());
+    announce(&mut transport, address, /* authenticate */ false).await?;
+    Ok(transport)
+}
+
+/// Connect to a node as a member of the committee, running the handshake of `authenticator`.
+pub async fn connect_authenticated(
+    address: SocketAddr,
+    authenticator: &Authenticator,
+) -> Result<Framed<TcpStream, Codec>, NetworkError> {
+    let stream = TcpStream::connect(address)
+        .await
+        .map_err(|e| NetworkError::FailedToConnect(address, /* retry */ 0, e))?;
+    let mut transport = Framed::new(stream, Codec::new());
+    announce(&mut transport, address, /* authenticate */ true).await?;
+    authenticator.connect(&mut transport, address).await?;
+    Ok(transport)
+}
+
+/// Answer the preamble of a new incoming connection from `address`, and return whether the peer runs
+/// the handshake next. The answer tells the peer our version even if it is not the same.
+pub async fn answer<T: AsyncRead + AsyncWrite + Unpin>(
+    transport: &mut Framed<T, Codec>,
+    address: SocketAddr,
+) -> Result<bool, NetworkError> {
+    timeout(PREAMBLE_TIMEOUT, async {
+        let preamble: Preamble = receive(transport, address).await.map_err(|_| NetworkError::InvalidPreamble(address))?;
+        if preamble.magic != MAGIC {
+            return Err(NetworkError::InvalidPreamble(address));
+        }
+        send(transport, &Preamble::new(false), address).await?;
+        preamble.check(address)?;
+        Ok(preamble.authenticate)
+    })
+    .await
+    .unwrap_or(Err(NetworkError::InvalidPreamble(address)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::sink::SinkExt as _;
+
+    #[tokio::test]
+    async fn test_version_mismatch() {
+        let address: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let (left, right) = tokio::io::duplex(1_024);
+        let (mut left, mut right) = (Framed::new(left, Codec::new()), Framed::new(right, Codec::new()));
+        let (announced, answered) = tokio::join!(announce(&mut left, address, true), answer(&mut right, address));
+        assert!(announced.is_ok());
+        assert!(answered.unwrap());
+
+        // A node of the next version is refused on both sides.
+        let (left, right) = tokio::io::duplex(1_024);
+        let (mut left, mut right) = (Framed::new(left, Codec::new()), Framed::new(right, Codec::new()));
+        let newer = Preamble { version: PROTOCOL_VERSION + 1, ..Preamble::new(false) };
+        let (_, answered) = tokio::join!(send(&mut left, &newer, address), answer(&mut right, address));
+        assert!(matches!(answered, Err(NetworkError::VersionMismatch(_, version, _)) if version == PROTOCOL_VERSION + 1));
+        let reply: Preamble = receive(&mut left, address).await.unwrap();
+        assert_eq!(reply.version, PROTOCOL_VERSION);
+
+        // So is a peer sending messages right away, like nodes before the preamble.
+        let (left, right) = tokio::io::duplex(1_024);
+        let (mut left, mut right) = (Framed::new(left, Codec::new()), Framed::new(right, Codec::new()));
+        let (_, answered) = tokio::join!(left.send(Bytes::from("vertex")), answer(&mut right, address));
+        assert!(matches!(answered, Err(NetworkError::InvalidPreamble(_))));
+    }
+}
@@ -1,22 +1,48 @@
+mod payload;
+
+use std::fs::File;
 use std::net::SocketAddr;
 use std::str::FromStr;
-use anyhow::{Context, Result};
-use bytes::BufMut as _;
-use bytes::BytesMut;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use bytes::{Bytes, BytesMut};
 use clap::{App, AppSettings, crate_name, crate_version};
 use env_logger::Env;
 use futures::sink::SinkExt as _;
-use log::{info};
+use futures::stream::StreamExt as _;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+use log::{debug, info, warn};
+use model::committee::Committee;
+use model::message::Message;
+use model::transaction::{CommitPosition, Receipt, SignedTransaction};
+use network::{version, Codec};
+use payload::{Content, SizeDistribution};
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
 use tokio::net::TcpStream;
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio::time::{sleep_until, timeout, Instant};
+use tokio_util::codec::Framed;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let matches = App::new(crate_name!())
         .version(crate_version!())
-        .args_from_usage("<ADDR> 'The network address of the node where to send txs'")
-        .args_from_usage("--TRANSACTION_COUNT=[COUNT] 'The number of transactions to send, default 10000'")
-        .args_from_usage("--TX_SIZE=[SIZE] 'The size of each transaction, default 128'")
+        .args_from_usage("[ADDR]... 'The network addresses of the nodes where to send txs'")
+        .args_from_usage("--committee=[PATH] 'Send the txs to every node of the committee file instead'")
+        .args_from_usage("--TRANSACTION_COUNT=[COUNT] 'The number of transactions to send without --rate, default 10000'")
+        .args_from_usage("--TX_SIZE=[SIZE] 'The size of each transaction in bytes, signature included, default 128'")
+        .args_from_usage("--size_distribution=[DISTRIBUTION] 'fixed, uniform or zipf, for sizes from --min_size to TX_SIZE, default fixed'")
+        .args_from_usage("--min_size=[SIZE] 'The smallest size of the uniform and zipf distributions, default 0'")
+        .args_from_usage("--zipf_exponent=[EXPONENT] 'The exponent of the zipf distribution, default 1'")
+        .args_from_usage("--content=[CONTENT] 'zeros, random or compressible, what transactions are padded with, default zeros'")
+        .args_from_usage("--trace=[PATH] 'Replay the times and sizes of the transactions of a trace file instead'")
+        .args_from_usage("--rate=[TX/S] 'Send the transactions at this rate for the warmup and the duration instead'")
+        .args_from_usage("--duration=[SECONDS] 'How long to send at the rate once warmed up, default 30'")
+        .args_from_usage("--warmup=[SECONDS] 'How long to send at the rate before measuring it, default 0'")
+        .args_from_usage("--connections=[COUNT] 'The number of connections, spread over the nodes, default one per node'")
+        .args_from_usage("--raw 'Send the transactions unsigned, for the nodes run with --raw_transactions'")
+        .args_from_usage("--receipt_timeout=[SECONDS] 'How long to wait for the commit of the transactions once they are sent, default 10'")
         .setting(AppSettings::ArgRequiredElseHelp)
         .get_matches();
 
@@ -24,60 +50,299 @@ async fn main() -> Result<()> {
         .format_timestamp_millis()
         .init();
 
-    let target = matches
-        .value_of("ADDR")
-        .unwrap()
-        .parse::<SocketAddr>()
-        .context("Invalid socket address format")?;
+    let targets = match matches.value_of("committee") {
+        Some(path) => {
+            let mut committee: Committee = serde_json::from_reader(File::open(path)?)?;
+            committee.resolve().context("Failed to resolve the addresses of the committee")?;
+            committee.get_node_ids().into_iter().filter_map(|id| committee.get_node_address(id)).collect()
+        }
+        None => matches
+            .values_of("ADDR")
+            .into_iter()
+            .flatten()
+            .map(|address| address.parse::<SocketAddr>().context("Invalid socket address format"))
+            .collect::<Result<Vec<_>>>()?,
+    };
+    if targets.is_empty() {
+        return Err(anyhow!("No node to send the transactions to"));
+    }
 
-    let transaction_count = u64::from_str(matches.value_of("TRANSACTION_COUNT").unwrap_or("10000"))?;
     let tx_size = usize::from_str(matches.value_of("TX_SIZE").unwrap_or("128"))?;
+    let connections = match matches.value_of("connections") {
+        Some(connections) => usize::from_str(connections)?.max(1),
+        None => targets.len(),
+    };
+    let sizes = SizeDistribution::parse(
+        matches.value_of("size_distribution").unwrap_or("fixed"),
+        usize::from_str(matches.value_of("min_size").unwrap_or("0"))?,
+        tx_size,
+        f64::from_str(matches.value_of("zipf_exponent").unwrap_or("1"))?,
+    )?;
+    let content = Content::from_str(matches.value_of("content").unwrap_or("zeros"))?;
+    let receipt_timeout = Duration::from_secs(u64::from_str(matches.value_of("receipt_timeout").unwrap_or("10"))?);
+    let load = match (matches.value_of("trace"), matches.value_of("rate")) {
+        (Some(path), _) => Load::Trace(Arc::new(payload::read_trace(path)?)),
+        (None, Some(rate)) => Load::Rate {
+            rate: u64::from_str(rate)?.max(1),
+            warmup: Duration::from_secs(u64::from_str(matches.value_of("warmup").unwrap_or("0"))?),
+            duration: Duration::from_secs(u64::from_str(matches.value_of("duration").unwrap_or("30"))?),
+        },
+        (None, None) => Load::Count(u64::from_str(matches.value_of("TRANSACTION_COUNT").unwrap_or("10000"))?),
+    };
 
-    info!("Node address: {}", target);
-    info!("Transaction count: {}", transaction_count);
-    info!("Transaction size: {}", tx_size);
+    info!("Node addresses: {:?}", targets);
+    match &load {
+        Load::Count(count) => info!("Transaction count: {}", count),
+        Load::Trace(trace) => info!("Transactions of the trace: {}", trace.len()),
+        Load::Rate { rate, warmup, duration } => {
+            info!("Transaction rate: {} tx/s for {} s after {} s of warmup", rate, duration.as_secs(), warmup.as_secs())
+        }
+    }
+    info!("Transaction size: {} ({:?}, {:?})", tx_size, sizes, content);
+    info!("Connections: {}", connections);
 
     let client = Client {
-        target,
-        transaction_count,
-        tx_size,
+        targets,
+        connections,
+        load,
+        sizes,
+        content,
+        raw: matches.is_present("raw"),
+        receipt_timeout,
     };
 
     // Start the benchmark.
     client.send().await.context("Failed to submit transactions")
 }
 
+/// How many transactions the client sends, and when.
+#[derive(Clone, Debug)]
+enum Load {
+    /// A number of transactions, sent as fast as the nodes read them.
+    Count(u64),
+    /// Transactions at a fixed rate in tx/s, whether the nodes keep up or not, during a warmup
+    /// period and then during the measured one.
+    Rate { rate: u64, warmup: Duration, duration: Duration },
+    /// Transactions at the times and of the sizes of a trace.
+    Trace(Arc<Vec<(Duration, usize)>>),
+}
+
+#[derive(Clone)]
 struct Client {
-    target: SocketAddr,
-    transaction_count: u64,
-    tx_size: usize,
+    targets: Vec<SocketAddr>,
+    connections: usize,
+    load: Load,
+    sizes: SizeDistribution,
+    content: Content,
+    raw: bool,
+    receipt_timeout: Duration,
+}
+
+/// Receipts of the node for the transactions of a connection.
+#[derive(Clone, Copy, Default)]
+struct Receipts {
+    queued: u64,
+    accepted: u64,
+    rejected: u64,
+    committed: u64,
+    last_commit: Option<CommitPosition>,
+    /// Number of transactions sent, once they all are.
+    expected: Option<u64>,
+}
+
+impl Receipts {
+    fn complete(&self) -> bool {
+        self.expected == Some(self.rejected + self.committed)
+    }
+}
+
+/// What a connection did, for the summary of the client.
+#[derive(Default)]
+struct Report {
+    sent: u64,
+    /// Total size of the transactions sent.
+    bytes: u64,
+    /// Transactions scheduled after the warmup.
+    measured: u64,
+    /// Longest delay of a transaction behind its schedule.
+    max_lag: Duration,
+    finished: Option<Instant>,
+    receipts: Receipts,
 }
 
 impl Client {
     pub async fn send(&self) -> Result<()> {
-        let TRANSACTION_COUNT: u64 = self.transaction_count.clone();
-        let TX_SIZE: usize = self.tx_size.clone();
+        // Connect first, so the schedule does not start with the time it takes.
+        let mut transports = Vec::with_capacity(self.connections);
+        for index in 0..self.connections {
+            let target = self.targets[index % self.targets.len()];
+            let transport = version::connect(target)
+                .await
+                .context(format!("failed to connect to {}", target))?;
+            transports.push(transport);
+        }
 
-        let stream = TcpStream::connect(self.target)
-            .await
-            .context(format!("failed to connect to {}", self.target))?;
+        info!("Start sending transactions");
 
-        let mut tx = BytesMut::with_capacity(TX_SIZE);
-        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+        let start = Instant::now();
+        let handles: Vec<_> = transports
+            .into_iter()
+            .enumerate()
+            .map(|(index, transport)| tokio::spawn(self.clone().run(index, transport, start)))
+            .collect();
+        let mut reports = Vec::with_capacity(handles.len());
+        for handle in handles {
+            reports.push(handle.await??);
+        }
 
-        info!("Start sending transactions");
+        let sent: u64 = reports.iter().map(|report| report.sent).sum();
+        let bytes: u64 = reports.iter().map(|report| report.bytes).sum();
+        info!("Sent {} transactions of {} bytes over {} connections", sent, bytes, self.connections);
+        if let Load::Rate { rate, warmup, .. } = self.load {
+            let measured: u64 = reports.iter().map(|report| report.measured).sum();
+            let finished = reports.iter().filter_map(|report| report.finished).max().unwrap_or(start);
+            let elapsed = finished.saturating_duration_since(start + warmup).as_secs_f64();
+            if elapsed > 0.0 {
+                let achieved = measured as f64 / elapsed;
+                info!(
+                    "Achieved {:.0} tx/s of the {} tx/s targeted, {:.1}% short, over {:.1} s after the warmup",
+                    achieved,
+                    rate,
+                    (100.0 * (1.0 - achieved / rate as f64)).max(0.0),
+                    elapsed
+                );
+            }
+        }
+        if !matches!(self.load, Load::Count(_)) {
+            let max_lag = reports.iter().map(|report| report.max_lag).max().unwrap_or_default();
+            info!("Transactions were sent at most {} ms behind schedule", max_lag.as_millis());
+        }
+
+        let mut receipts = Receipts::default();
+        for report in &reports {
+            receipts.queued += report.receipts.queued;
+            receipts.accepted += report.receipts.accepted;
+            receipts.rejected += report.receipts.rejected;
+            receipts.committed += report.receipts.committed;
+            receipts.last_commit = receipts.last_commit.into_iter().chain(report.receipts.last_commit).max_by_key(|position| position.sequence);
+        }
+        info!(
+            "{} transactions accepted, {} rejected and {} committed, {} of them queued first",
+            receipts.accepted, receipts.rejected, receipts.committed, receipts.queued
+        );
+        if let Some(position) = receipts.last_commit {
+            info!("Last commit in wave {} at position {}", position.wave, position.sequence);
+        }
+        Ok(())
+    }
+
+    /// Send the transactions of a connection. The connections take turns numbering the
+    /// transactions, which are scheduled at the rate from a common start, so they interleave.
+    async fn run(
+        self,
+        index: usize,
+        transport: Framed<TcpStream, Codec>,
+        start: Instant,
+    ) -> Result<Report> {
+        let (mut transport, mut replies) = transport.split();
+
+        // The receipts are read while sending, so they do not back up at the node.
+        let receipts = Arc::new(Mutex::new(Receipts::default()));
+        let reader = {
+            let receipts = receipts.clone();
+            tokio::spawn(async move {
+                while let Some(Ok(reply)) = replies.next().await {
+                    let mut receipts = receipts.lock().unwrap();
+                    match Receipt::from_bytes(&reply) {
+                        Ok(Receipt::Queued(_)) => receipts.queued += 1,
+                        Ok(Receipt::Accepted(_)) => receipts.accepted += 1,
+                        Ok(Receipt::Rejected(_, reason)) => {
+                            warn!("Transaction rejected: {}", reason);
+                            receipts.rejected += 1;
+                        }
+                        Ok(Receipt::Committed(_, position)) => {
+                            receipts.committed += 1;
+                            receipts.last_commit = Some(position);
+                        }
+                        Err(e) => warn!("Invalid receipt: {}", e),
+                    }
+                    if receipts.complete() {
+                        break;
+                    }
+                }
+            })
+        };
 
-        for c in 0..TRANSACTION_COUNT {
-            info!("Sending sample transaction {}", c);
+        // Every connection signs with a key of its own, so its nonces increase.
+        let keypair = (!self.raw).then(|| {
+            let secret = SecretKey::from_bytes(&rand::random::<[u8; 32]>()).unwrap();
+            Keypair { public: PublicKey::from(&secret), secret }
+        });
 
-            tx.put_u8(0u8); // Sample txs start with 0.
-            tx.put_u64(c); // This counter identifies the tx.
-            // tx.resize(TX_SIZE, 0u8);
-            let bytes = tx.split().freeze();
+        // The signed transactions are larger than their payload by a fixed amount, which is left
+        // out of the payload so the transactions have the size asked for.
+        let overhead = keypair
+            .as_ref()
+            .map_or(0, |keypair| SignedTransaction::new(keypair, 0, Vec::new()).to_transaction().len());
+        let mut rng = StdRng::from_entropy();
+        let mut tx = BytesMut::new();
+        let mut report = Report::default();
+
+        for c in (index as u64..).step_by(self.connections) {
+            let size = match &self.load {
+                Load::Count(count) if c >= *count => break,
+                Load::Count(_) => self.sizes.sample(&mut rng),
+                Load::Rate { rate, warmup, duration } => {
+                    let (rate, warmup, duration) = (*rate, *warmup, *duration);
+                    // Deadlines are taken from the start rather than the previous transaction, so
+                    // the timer resolution does not drift the rate, and late transactions are sent
+                    // right away to catch up.
+                    let deadline = start + Duration::from_secs_f64(c as f64 / rate as f64);
+                    if deadline >= start + warmup + duration {
+                        break;
+                    }
+                    sleep_until(deadline).await;
+                    report.max_lag = report.max_lag.max(Instant::now().duration_since(deadline));
+                    if deadline >= start + warmup {
+                        report.measured += 1;
+                    }
+                    self.sizes.sample(&mut rng)
+                }
+                Load::Trace(trace) => match trace.get(c as usize) {
+                    Some((time, size)) => {
+                        let deadline = start + *time;
+                        sleep_until(deadline).await;
+                        report.max_lag = report.max_lag.max(Instant::now().duration_since(deadline));
+                        *size
+                    }
+                    None => break,
+                },
+            };
+            debug!("Sending sample transaction {}", c);
+
+            payload::fill(&mut tx, c, size.saturating_sub(overhead), self.content, &mut rng);
+            let transaction = match &keypair {
+                Some(keypair) => SignedTransaction::new(keypair, c, tx.split().to_vec()).to_transaction(),
+                None => tx.split().to_vec(),
+            };
+            report.bytes += transaction.len() as u64;
+            let bytes = Bytes::from(Message::Transaction(transaction).to_bytes());
 
             transport.send(bytes).await?;
+            report.sent += 1;
         }
+        report.finished = Some(Instant::now());
 
-        Ok(())
+        // Workers do not send commit receipts, so their clients always wait for the timeout.
+        {
+            let mut receipts = receipts.lock().unwrap();
+            receipts.expected = Some(report.sent);
+            if receipts.complete() {
+                reader.abort();
+            }
+        }
+        let _ = timeout(self.receipt_timeout, reader).await;
+        report.receipts = *receipts.lock().unwrap();
+        Ok(report)
     }
 }
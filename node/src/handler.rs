@@ -0,0 +1,150 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use log::{debug, info, warn};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
+
+use model::block::{Block, BlockHash, Transaction};
+use model::chunk::Chunk;
+use model::committee::Id;
+use model::epoch::Epochs;
+use model::message::{AdminCommand, Message};
+use model::vertex::Vertex;
+use network::{Emulator, MessageHandler, Partition, PeerId, Replier, Writer};
+use transaction::submit;
+
+use crate::set_partition;
+
+/// Handler of every message sent to the port of the node.
+#[derive(Clone)]
+pub struct NodeHandler {
+    pub node_id: Id,
+    pub vertex_to_consensus_sender: Sender<Vertex>,
+    pub block_sender: Sender<Block>,
+    pub block_reply_sender: Sender<Block>,
+    pub block_request_sender: Sender<(BlockHash, Id)>,
+    pub batch_sender: Sender<(BlockHash, bool)>,
+    pub transaction_sender: Sender<(Transaction, Replier)>,
+    pub chunk_sender: Sender<Chunk>,
+    /// Committees giving the key of the authenticated peers.
+    pub epochs: watch::Receiver<Epochs>,
+    /// Whether the node authenticates its peers, so that only they may send vertices and blocks.
+    pub authenticated: bool,
+    /// Emulated network switched by the admin commands, if the node accepts them.
+    pub admin: Option<Emulator>,
+    /// Set by consensus while the node pretends to be failing, which drops every message but still
+    /// looks at the round of the vertices.
+    pub drop_packets: Arc<AtomicBool>,
+}
+
+impl NodeHandler {
+    /// Whether the message may come from an anonymous peer, like a client. Admin commands need the
+    /// key of a member of the committee.
+    fn is_public(message: &Message) -> bool {
+        matches!(message, Message::Transaction(_))
+    }
+
+    async fn handle_vertex(&self, vertex: Vertex, peer: Option<PeerId>) {
+        // Nodes only send their own vertices, so an authenticated peer cannot forge the others'.
+        if let Some(peer) = peer {
+            if self.epochs.borrow().committee_at(vertex.round()).get_node_key(peer) != Some(vertex.owner()) {
+                warn!("Drop the vertex {} which peer {} does not own", vertex, peer);
+                return;
+            }
+        }
+        self.vertex_to_consensus_sender.send(vertex).await.expect("Failed to send vertex to consensus");
+    }
+
+    fn handle_admin(&self, command: AdminCommand) -> String {
+        let emulator = match &self.admin {
+            Some(emulator) => emulator,
+            None => return "this node does not accept admin commands".to_string(),
+        };
+        match command {
+            AdminCommand::Partition(None) => set_partition(emulator, None),
+            AdminCommand::Partition(Some(partition)) => match partition.parse::<Partition>() {
+                Ok(partition) => set_partition(emulator, Some(partition)),
+                Err(e) => return e.to_string(),
+            },
+        }
+        "ok".to_string()
+    }
+}
+
+#[async_trait]
+impl MessageHandler for NodeHandler {
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        serialized: Bytes,
+        peer: Option<PeerId>,
+        replier: &Replier,
+    ) -> Result<(), Box<dyn Error>> {
+        let message = Message::from_bytes(&serialized)?;
+        if self.authenticated && peer.is_none() && !Self::is_public(&message) {
+            warn!("Drop a message of an unauthenticated peer which only the nodes may send");
+            if let Message::Admin(_) = message {
+                writer.send(Bytes::from("admin commands need the key of a node")).await?;
+            }
+            return Ok(());
+        }
+        if self.drop_packets.load(Ordering::Relaxed) {
+            // Consensus drops the vertices too, once it has seen their round.
+            if let Message::Vertex(vertex) = message {
+                self.handle_vertex(vertex, peer).await;
+            }
+            return Ok(());
+        }
+        match message {
+            Message::Vertex(vertex) => {
+                let _ = writer.send(Bytes::from("Ack")).await;
+                self.handle_vertex(vertex, peer).await;
+            }
+            Message::Block(block) => {
+                let _ = writer.send(Bytes::from("Ack")).await;
+                info!("Received a block with {} transactions and send it to consensus", block.transactions.len());
+                self.block_sender.send(block).await.expect("Failed to send block");
+            }
+            Message::BlockReply(block) => {
+                let _ = writer.send(Bytes::from("Ack")).await;
+                self.block_reply_sender.send(block).await.expect("Failed to send block reply");
+            }
+            Message::BlockRequest(block_hash, id) => {
+                let _ = writer.send(Bytes::from("Ack")).await;
+                // The block goes to the requesting node, which cannot be another one than the peer.
+                match peer {
+                    Some(peer) if peer != id => warn!("Drop the block request of node {} sent by peer {}", id, peer),
+                    _ => self.block_request_sender.send((block_hash, id)).await.expect("Failed to send block request"),
+                }
+            }
+            Message::Batch(block_hash, sealed) => {
+                let _ = writer.send(Bytes::from("Ack")).await;
+                // Workers authenticate with the key of their node.
+                match peer {
+                    Some(peer) if peer != self.node_id => warn!("Drop a batch reported by peer {}, which is not a worker of this node", peer),
+                    _ => self.batch_sender.send((block_hash, sealed)).await.expect("Failed to send batch"),
+                }
+            }
+            Message::Chunk(chunk) => {
+                let _ = writer.send(Bytes::from("Ack")).await;
+                self.chunk_sender.send(chunk).await.expect("Failed to send chunk");
+            }
+            Message::Transaction(transaction) => {
+                debug!("Received transaction to process {:?}", transaction);
+                submit(&self.transaction_sender, writer, transaction, replier).await;
+            }
+            Message::Admin(command) => {
+                info!("Received admin command {:?}", command);
+                let reply = self.handle_admin(command);
+                writer.send(Bytes::from(reply)).await?;
+            }
+            Message::Committed(..) => warn!("Drop committed batches, which only workers handle"),
+        }
+        Ok(())
+    }
+}
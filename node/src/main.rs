@@ -1,20 +1,43 @@
-use anyhow::{Result};
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
 use clap::{App, AppSettings, ArgMatches, SubCommand};
 use env_logger::Env;
-use log::info;
-use tokio::sync::mpsc::{channel, Receiver};
-
-use consensus::Consensus;
-use model::block::Block;
-use model::committee::{Committee, Id};
-use model::vertex::Vertex;
-use transaction::TransactionCoordinator;
+use log::{info, warn};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::watch;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{interval, sleep_until, Duration, Instant};
+
+use checker::{Checker, LogRecord, LogWriter, NodeLog, VertexRecord};
+use consensus::{parse_adversary, parse_schedule, Consensus, FaultPeriod, Payload, ProposalBudget};
+use model::block::{Block, BlockHash, Transaction};
+use model::chunk::Chunk;
+use ed25519_dalek::Keypair;
+use model::committee::{Committee, Id, Validator, WorkerId};
+use model::epoch::{Epochs, Reconfiguration};
+use model::message::{AdminCommand, Message};
+use model::transaction::CommitPosition;
+use model::vertex::{Vertex, VertexMessage};
+use network::{version, Authenticator, Emulator, Partition, Receiver as NetworkReceiver, Replier, Scenario, WanConfig};
+use transaction::{BatchCommitter, BatchConfig, Dissemination, MempoolConfig, TransactionCoordinator, Worker};
 use vertex::vertex_coordinator::VertexCoordinator;
 
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 use serde_json;
 
+use crate::handler::NodeHandler;
+use crate::stats::{now_millis, Statistics};
+
+mod handler;
+mod stats;
+
 pub const DEFAULT_CHANNEL_CAPACITY: usize = 1000;
 
 #[tokio::main]
@@ -27,9 +50,68 @@ async fn main() -> Result<()> {
                 .about("Run a node")
                 .args_from_usage("--id=<INT> 'Node id'")
                 .args_from_usage("--pretend_failure=<PF> 'pretend to be a faulty node'")
+                .args_from_usage("--adversary=[STRATEGY] 'Byzantine strategy of the node: honest, drop, silent, equivocate, withhold:<ids>, stale_parents, delay_leader:<rounds>, malformed or wave_withholding'")
+                .args_from_usage("--fault_schedule=[PATH] 'Path to a JSON file giving the strategies of the nodes during ranges of rounds'")
+                .args_from_usage("--wan=[PATH] 'Path to a JSON file giving the latency, jitter, loss and bandwidth of the emulated WAN'")
+                .args_from_usage("--scenario=[PATH] 'Path to a JSON file giving the partitions of the network at given rounds or times'")
+                .args_from_usage("--admin 'Accept partition commands (see `partition --help`)'")
                 .args_from_usage("--committee=<PATH> 'Path to committee JSON file'")
+                .args_from_usage("--key=[PATH] 'Path to the hex key pair of the node, which then authenticates its connections with the other nodes and rejects unknown peers'")
+                .args_from_usage("--encrypt 'Encrypt the connections with the other nodes, which needs --key'")
                 .args_from_usage("--channel_capacity=[CAPACITY] 'Channel capacity'")
                 .args_from_usage("--batch_size=[SIZE] 'Batch size'")
+                .args_from_usage("--batch_bytes=[BYTES] 'Size of the transactions sealing a block before it reaches the batch size (500000 by default)'")
+                .args_from_usage("--batch_delay=[MS] 'Longest time a transaction waits for its block to be sealed (100 by default)'")
+                .args_from_usage("--adaptive_batching=[TARGET_MS] 'Adapt the batch size, up to --batch_size, for the blocks to be committed within the target latency'")
+                .args_from_usage("--mempool_capacity=[COUNT] 'Number of uncommitted transactions beyond which the clients wait (100000 by default)'")
+                .args_from_usage("--mempool_bytes=[BYTES] 'Size of the uncommitted transactions beyond which the clients wait (50000000 by default)'")
+                .args_from_usage("--mempool_ttl=[SECONDS] 'Time after which uncommitted transactions may be submitted again and committed ones are forgotten (60 by default)'")
+                .args_from_usage("--raw_transactions 'Also accept transactions which are not signed by their client'")
+                .args_from_usage("--max_vertex_blocks=[COUNT] 'Maximum number of blocks proposed by a vertex (10 by default)'")
+                .args_from_usage("--max_vertex_bytes=[BYTES] 'Maximum size of the transactions proposed by a vertex, the oldest block going even if it is larger (500000 by default)'")
+                .args_from_usage("--dissemination=[MODE] 'How the blocks are sent: full (default) or erasure, as erasure-coded chunks'")
+                .args_from_usage("--commit_log=[PATH] 'Path of the file where broadcast and committed vertices are recorded'")
+                .args_from_usage("--stats=[PATH] 'Path of the JSON file where the throughput and latency of the committed transactions are written, periodically and when the node is stopped'")
+                .args_from_usage("--stats_interval=[SECONDS] 'Time between two writes of the statistics (10 by default)'")
+        )
+        .subcommand(
+            SubCommand::with_name("worker")
+                .about("Run a worker of a node, which batches the transactions sent to its own address")
+                .args_from_usage("--id=<INT> 'Id of the node of the worker'")
+                .args_from_usage("--worker=<INDEX> 'Index of the worker among the workers of the node, from 0'")
+                .args_from_usage("--committee=<PATH> 'Path to committee JSON file'")
+                .args_from_usage("--key=[PATH] 'Path to the hex key pair of the node (see `run --help`)'")
+                .args_from_usage("--encrypt 'Encrypt the connections with the other workers and the node, which needs --key'")
+                .args_from_usage("--batch_size=[SIZE] 'Batch size'")
+                .args_from_usage("--batch_bytes=[BYTES] 'Size of the transactions sealing a block before it reaches the batch size (500000 by default)'")
+                .args_from_usage("--batch_delay=[MS] 'Longest time a transaction waits for its block to be sealed (100 by default)'")
+                .args_from_usage("--mempool_capacity=[COUNT] 'Number of transactions waiting for a block beyond which the clients wait (100000 by default)'")
+                .args_from_usage("--mempool_bytes=[BYTES] 'Size of the transactions waiting for a block beyond which the clients wait (50000000 by default)'")
+                .args_from_usage("--mempool_ttl=[SECONDS] 'Time during which transactions already batched are recognized (60 by default)'")
+                .args_from_usage("--raw_transactions 'Also accept transactions which are not signed by their client'")
+                .args_from_usage("--dissemination=[MODE] 'How the blocks are sent: full (default) or erasure, as erasure-coded chunks'")
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Check agreement, validity and liveness across the commit logs of the nodes")
+                .args_from_usage("--max_commit_gap=[MS] 'Maximum time between two consecutive commits of a node'")
+                .args_from_usage("<LOGS>... 'Commit logs written by the nodes with --commit_log'")
+        )
+        .subcommand(
+            SubCommand::with_name("partition")
+                .about("Partition the network of running nodes, or heal it")
+                .args_from_usage("<PARTITION> 'Groups of node ids which can only talk within their group, like 1,2|3,4, or heal'")
+                .args_from_usage("--key=[PATH] 'Path to the hex key pair of a node, needed by nodes started with --key'")
+                .args_from_usage("--committee=[PATH] 'Path to the committee JSON file, which gives the keys of the nodes, with --key'")
+                .args_from_usage("<ADDRS>... 'Addresses of the nodes, started with --admin'")
+        )
+        .subcommand(
+            SubCommand::with_name("reconfigure")
+                .about("Submit a transaction replacing the committee, which takes over a few waves after it is committed")
+                .args_from_usage("--epoch=<EPOCH> 'Epoch started by the new committee, the one following the last epoch'")
+                .args_from_usage("--committee=<PATH> 'Path to the JSON file of the new committee'")
+                .args_from_usage("--keys=<PATHS> 'Comma-separated paths to the key pairs of nodes holding a quorum of the stake of the current committee, like node_1.key,node_2.key,node_3.key, which sign the new committee'")
+                .args_from_usage("<ADDR> 'Address of a node of the current committee'")
         )
         .subcommand(
           SubCommand::with_name("generate")
@@ -37,8 +119,27 @@ async fn main() -> Result<()> {
               .args_from_usage("--node_count=[COUNT] 'Number of nodes'")
               .args_from_usage("--faulty_count=[FCOUNT] 'Number of faulties'")
               .args_from_usage("--faulty_type=[FTYPE] 'Type of faulties, 1 represents simulating regular adversaries, 2 represents simulating special adversaries (as described in the paper)'")
+              .args_from_usage("--adversary=[STRATEGY] 'Byzantine strategy of the faulties, overrides --faulty_type (see `run --help`)'")
+              .args_from_usage("--fault_schedule=[PATH] 'Fault schedule passed to every node (see `run --help`)'")
+              .args_from_usage("--wan=[PATH] 'WAN emulation config passed to every node (see `run --help`)'")
+              .args_from_usage("--encrypt 'Encrypt the connections between the nodes'")
+              .args_from_usage("--scenario=[PATH] 'Partition scenario passed to every node (see `run --help`)'")
+              .args_from_usage("--hosts=[PATH] 'File listing the hosts of the nodes, one name or IP per line, assigned to the nodes round-robin (127.0.0.1 if missing)'")
+              .args_from_usage("--workers=[COUNT] 'Number of workers of every node, run in processes of their own (none by default)'")
+              .args_from_usage("--stakes=[STAKES] 'Comma-separated stakes of the nodes from node 1 on, 1 for the missing ones'")
               .args_from_usage("--channel_capacity=[CAPACITY] 'Channel capacity'")
               .args_from_usage("--batch_size=[SIZE] 'Batch size'")
+              .args_from_usage("--batch_bytes=[BYTES] 'Size of the transactions sealing a block before it reaches the batch size (500000 by default)'")
+              .args_from_usage("--batch_delay=[MS] 'Longest time a transaction waits for its block to be sealed (100 by default)'")
+              .args_from_usage("--adaptive_batching=[TARGET_MS] 'Adapt the batch size of the nodes to their commit latency (see `run --help`)'")
+              .args_from_usage("--mempool_capacity=[COUNT] 'Number of pending transactions of the nodes and workers beyond which the clients wait (see `run --help`)'")
+              .args_from_usage("--mempool_bytes=[BYTES] 'Size of the pending transactions of the nodes and workers beyond which the clients wait (see `run --help`)'")
+              .args_from_usage("--mempool_ttl=[SECONDS] 'Time the nodes and workers remember transactions for (see `run --help`)'")
+              .args_from_usage("--raw_transactions 'Make the nodes and workers also accept transactions which are not signed by their client'")
+              .args_from_usage("--stats_interval=[SECONDS] 'Time between two writes of the statistics of the nodes to stats_<id>.json (see `run --help`)'")
+              .args_from_usage("--max_vertex_blocks=[COUNT] 'Maximum number of blocks proposed by a vertex (10 by default)'")
+              .args_from_usage("--max_vertex_bytes=[BYTES] 'Maximum size of the transactions proposed by a vertex (500000 by default)'")
+              .args_from_usage("--dissemination=[MODE] 'How the blocks are sent: full (default) or erasure, as erasure-coded chunks'")
         )
         .get_matches();
 
@@ -47,6 +148,10 @@ async fn main() -> Result<()> {
 
     match matches.subcommand() {
         ("run", Some(sub_matches)) => run(sub_matches).await?,
+        ("worker", Some(sub_matches)) => worker(sub_matches).await?,
+        ("check", Some(sub_matches)) => check(sub_matches)?,
+        ("partition", Some(sub_matches)) => partition(sub_matches).await?,
+        ("reconfigure", Some(sub_matches)) => reconfigure(sub_matches).await?,
         ("generate", Some(sub_matches)) => generate(sub_matches).await?,
         _ => unreachable!(),
     }
@@ -61,21 +166,13 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
     .unwrap_or("0")
     .parse::<usize>()
     .unwrap();
-    let mut ftype = 0;
-    if pretend_failure == 0 {
-      ftype = 0;
-    }else if pretend_failure == 1 {
-      ftype = 1;
-    }else if pretend_failure == 2 {
-      ftype = 2;
-    }
 
-    let mut pretend_failure = pretend_failure != 0;
-
-    if ftype == 2 {
-      pretend_failure = false;
-    }
-    
+    let strategy = match matches.value_of("adversary") {
+        Some(strategy) => strategy,
+        None if pretend_failure == 1 => "drop",
+        None if pretend_failure == 2 => "wave_withholding",
+        None => "honest",
+    };
 
     let channel_capacity = matches
     .value_of("channel_capacity")
@@ -83,38 +180,153 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
     .parse::<usize>()
     .unwrap();
 
-    let batch_size = matches
-    .value_of("batch_size")
-    .unwrap_or("10")
-    .parse::<usize>()
-    .unwrap();
+    let batch = batch_config(matches)?;
+    let mempool = mempool_config(matches)?;
+    let dissemination = matches.value_of("dissemination").unwrap_or("full").parse::<Dissemination>().map_err(|e| anyhow!(e))?;
+    let mut budget = ProposalBudget::default();
+    if let Some(max_blocks) = matches.value_of("max_vertex_blocks") {
+        budget.max_blocks = max_blocks.parse()?;
+    }
+    if let Some(max_bytes) = matches.value_of("max_vertex_bytes") {
+        budget.max_bytes = max_bytes.parse()?;
+    }
     let committee_file = matches.value_of("committee").unwrap();
+    let commit_log = match matches.value_of("commit_log") {
+        Some(path) => Some(LogWriter::create(path, node_id)?),
+        None => None,
+    };
+    let stats = match matches.value_of("stats") {
+        Some(path) => Some((path.to_string(), Duration::from_secs(matches.value_of("stats_interval").unwrap_or("10").parse()?))),
+        None => None,
+    };
 
     // Load the committee from the file.
-    let committee: Committee = serde_json::from_reader(File::open(committee_file)?)?;
-
-    let (vertex_output_sender, vertex_output_receiver) = channel::<Vertex>(channel_capacity);
-
-    let (vertex_to_broadcast_sender, vertex_to_broadcast_receiver) = channel::<Vertex>(channel_capacity);
+    let mut committee: Committee = serde_json::from_reader(File::open(committee_file)?)?;
+    committee.check_stakes().map_err(|e| anyhow!("Invalid committee {}: {}", committee_file, e))?;
+    committee.resolve().context("Failed to resolve the addresses of the committee")?;
+    let mut adversary = parse_adversary(strategy, node_id, &committee)
+        .ok_or_else(|| anyhow!("Unknown adversary strategy {}", strategy))?;
+    if let Some(path) = matches.value_of("fault_schedule") {
+        let mut schedule: HashMap<Id, Vec<FaultPeriod>> = serde_json::from_reader(File::open(path)?)?;
+        if let Some(periods) = schedule.remove(&node_id) {
+            adversary = parse_schedule(&periods, adversary, node_id, &committee)
+                .map_err(|e| anyhow!("Invalid fault schedule {}: {}", path, e))?;
+        }
+    }
+    // Set by consensus while the node pretends to be failing.
+    let drop_packets = Arc::new(AtomicBool::new(false));
+    // Updated by consensus when a new committee is scheduled or takes over.
+    let (epochs_sender, epochs_receiver) = watch::channel(Epochs::new(committee.clone()));
+    let authenticator = match matches.value_of("key") {
+        Some(path) => Some(
+            authenticator(path, node_id, &committee, epochs_receiver.clone())?
+                .with_encryption(matches.is_present("encrypt")),
+        ),
+        None if matches.is_present("encrypt") => return Err(anyhow!("--encrypt needs the key pair given by --key")),
+        None => None,
+    };
+
+    let scenario = match matches.value_of("scenario") {
+        Some(path) => {
+            let scenario: Scenario = serde_json::from_reader(File::open(path)?)?;
+            scenario.check()?;
+            Some(scenario)
+        }
+        None => None,
+    };
+    let wan = match matches.value_of("wan") {
+        Some(path) => {
+            let wan: WanConfig = serde_json::from_reader(File::open(path)?)?;
+            wan.check()?;
+            Some(wan)
+        }
+        // Partitions need the links to be emulated, without any delay if there is no WAN.
+        None if scenario.is_some() || matches.is_present("admin") => Some(WanConfig {
+            latency: vec![vec![0]],
+            ..WanConfig::default()
+        }),
+        None => None,
+    };
+    let emulator = match wan {
+        Some(wan) => {
+            let peers = committee
+                .validators
+                .iter()
+                .filter(|(id, _)| **id != node_id)
+                .filter_map(|(id, v)| Some((*id, v.socket_address()?)));
+            wan.emulator(node_id, peers)
+        }
+        None => Emulator::default(),
+    };
+    let round_events = match scenario {
+        Some(scenario) => spawn_scenario(&scenario, emulator.clone())?,
+        None => vec![],
+    };
+
+    let (vertex_output_sender, vertex_output_receiver) = channel::<(Vertex, Vec<Payload>, CommitPosition)>(channel_capacity);
+
+    let (vertex_to_broadcast_sender, vertex_to_broadcast_receiver) = channel::<VertexMessage>(channel_capacity);
+    let (consensus_to_broadcast_sender, consensus_to_broadcast_receiver) = channel::<VertexMessage>(channel_capacity);
     let (vertex_to_consensus_sender, vertex_to_consensus_receiver) = channel::<Vertex>(channel_capacity);
+    let (own_block_sender, own_block_receiver) = channel::<Block>(channel_capacity);
     let (block_sender, block_receiver) = channel::<Block>(channel_capacity);
+    let (block_reply_sender, block_reply_receiver) = channel::<Block>(channel_capacity);
+    let (batch_sender, batch_receiver) = channel::<(BlockHash, bool)>(channel_capacity);
+    let (block_request_sender, block_request_receiver) = channel::<(BlockHash, Id)>(channel_capacity);
+    let (transaction_sender, transaction_receiver) = channel::<(Transaction, Replier)>(channel_capacity);
+    let (chunk_sender, chunk_receiver) = channel::<Chunk>(channel_capacity);
+    let (commit_sender, commit_receiver) = channel::<(Block, CommitPosition)>(channel_capacity);
+
+    // Every message of the other nodes and of the clients arrives on the port of the node.
+    let address = committee.get_listen_address(node_id).ok_or_else(|| anyhow!("Node {} is not in the committee", node_id))?;
+    NetworkReceiver::spawn_authenticated(
+        address,
+        NodeHandler {
+            node_id,
+            vertex_to_consensus_sender,
+            block_sender: block_sender.clone(),
+            block_reply_sender,
+            block_request_sender,
+            batch_sender,
+            transaction_sender,
+            chunk_sender,
+            epochs: epochs_receiver.clone(),
+            authenticated: authenticator.is_some(),
+            admin: matches.is_present("admin").then(|| emulator.clone()),
+            drop_packets: drop_packets.clone(),
+        },
+        // The handler drops the packets itself, as consensus still needs the rounds of the vertices.
+        Arc::new(AtomicBool::new(false)),
+        emulator.clone(),
+        authenticator.clone(),
+    );
+    info!("Node {} listening to the messages on {}", node_id, address);
 
     VertexCoordinator::spawn(
         node_id,
-        //Committee::default(),
-        committee.clone(),
-        vertex_to_consensus_sender,
         vertex_to_broadcast_receiver,
-        pretend_failure
+        emulator.clone(),
+        epochs_receiver.clone(),
+        authenticator.clone(),
     );
 
+    // The workers of the node are told which of their batches it commits.
+    let workers: Vec<SocketAddr> = (0..).map_while(|worker| committee.get_worker_address(node_id, worker)).collect();
+    let batch_committer = (!workers.is_empty()).then(|| BatchCommitter::new(workers, authenticator.clone()));
+
     TransactionCoordinator::spawn(
         node_id,
-        //Committee::default(),
-        committee.clone(),
+        transaction_receiver,
+        chunk_receiver,
         block_sender,
-        batch_size,
-        pretend_failure
+        own_block_sender,
+        commit_receiver,
+        batch,
+        mempool,
+        dissemination,
+        emulator.clone(),
+        epochs_receiver,
+        authenticator,
     );
 
     Consensus::spawn(
@@ -122,16 +334,229 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
         //Committee::default(),
         committee.clone(),
         vertex_to_consensus_receiver,
-        vertex_to_broadcast_sender,
+        consensus_to_broadcast_sender,
         vertex_output_sender,
+        own_block_receiver,
         block_receiver,
-        ftype
+        block_reply_receiver,
+        batch_receiver,
+        block_request_receiver,
+        adversary,
+        budget,
+        drop_packets,
+        epochs_sender,
     );
 
-    wait_and_print_vertexs(vertex_output_receiver).await;
+    wait_and_print_vertexs(
+        vertex_output_receiver,
+        consensus_to_broadcast_receiver,
+        vertex_to_broadcast_sender,
+        commit_sender,
+        batch_committer,
+        commit_log,
+        round_events,
+        emulator,
+        node_id,
+        stats,
+    ).await;
+    Ok(())
+}
+
+/// Run a worker of a node, until the process is stopped.
+async fn worker(matches: &ArgMatches<'_>) -> Result<()> {
+    let node_id = matches.value_of("id").unwrap().parse::<Id>()?;
+    let worker = matches.value_of("worker").unwrap().parse::<WorkerId>()?;
+    let batch = batch_config(matches)?;
+    let mempool = mempool_config(matches)?;
+    let dissemination = matches.value_of("dissemination").unwrap_or("full").parse::<Dissemination>().map_err(|e| anyhow!(e))?;
+    let mut committee: Committee = serde_json::from_reader(File::open(matches.value_of("committee").unwrap())?)?;
+    committee.resolve().context("Failed to resolve the addresses of the committee")?;
+    let address = committee
+        .get_worker_listen_address(node_id, worker)
+        .ok_or_else(|| anyhow!("Node {} has no worker {} in the committee", node_id, worker))?;
+
+    // Workers do not follow the reconfigurations, they stay with the committee they started with.
+    let (_epochs_sender, epochs_receiver) = watch::channel(Epochs::new(committee.clone()));
+    let authenticator = match matches.value_of("key") {
+        Some(path) => Some(
+            authenticator(path, node_id, &committee, epochs_receiver.clone())?
+                .with_encryption(matches.is_present("encrypt")),
+        ),
+        None if matches.is_present("encrypt") => return Err(anyhow!("--encrypt needs the key pair given by --key")),
+        None => None,
+    };
+    Worker::spawn(node_id, worker, address, epochs_receiver, batch, mempool, dissemination, authenticator);
+    std::future::pending::<()>().await;
     unreachable!();
 }
 
+/// Limits sealing the blocks of the node or worker.
+fn batch_config(matches: &ArgMatches<'_>) -> Result<BatchConfig> {
+    let mut batch = BatchConfig::default();
+    if let Some(size) = matches.value_of("batch_size") {
+        batch.max_transactions = size.parse()?;
+    }
+    if let Some(bytes) = matches.value_of("batch_bytes") {
+        batch.max_bytes = bytes.parse()?;
+    }
+    if let Some(ms) = matches.value_of("batch_delay") {
+        batch.max_delay = Duration::from_millis(ms.parse()?);
+    }
+    if let Some(ms) = matches.value_of("adaptive_batching") {
+        batch.target_latency = Some(Duration::from_millis(ms.parse()?));
+    }
+    Ok(batch)
+}
+
+fn mempool_config(matches: &ArgMatches<'_>) -> Result<MempoolConfig> {
+    let mut mempool = MempoolConfig::default();
+    if let Some(count) = matches.value_of("mempool_capacity") {
+        mempool.max_transactions = count.parse()?;
+    }
+    if let Some(bytes) = matches.value_of("mempool_bytes") {
+        mempool.max_bytes = bytes.parse()?;
+    }
+    if let Some(seconds) = matches.value_of("mempool_ttl") {
+        mempool.ttl = Duration::from_secs(seconds.parse()?);
+    }
+    mempool.accept_raw = matches.is_present("raw_transactions");
+    Ok(mempool)
+}
+
+/// Authenticator of the node holding the key pair of the file, which accepts the members of the current
+/// committee and of the next ones.
+fn authenticator(path: &str, node_id: Id, committee: &Committee, epochs: watch::Receiver<Epochs>) -> Result<Authenticator> {
+    let keypair = read_keypair(path)?;
+    if committee.get_node_key(node_id) != Some(Validator::node_public_key(&keypair.public)) {
+        return Err(anyhow!("The key pair in {} is not the one of node {} in the committee", path, node_id));
+    }
+    Ok(Authenticator::new(keypair, move |key| {
+        let key = Validator::node_public_key(key);
+        epochs.borrow().current_and_next().find_map(|committee| committee.get_node_id(&key))
+    }))
+}
+
+/// Read the hex key pair of a node, written by `generate`.
+fn read_keypair(path: &str) -> Result<Keypair> {
+    let bytes = hex::decode(std::fs::read_to_string(path)?.trim())?;
+    Keypair::from_bytes(&bytes).map_err(|e| anyhow!("Invalid key pair in {}: {}", path, e))
+}
+
+/// Spawn a task switching the partition at the times of the scenario, and return the partitions to
+/// switch to at given rounds, in order.
+fn spawn_scenario(scenario: &Scenario, emulator: Emulator) -> Result<Vec<(u64, Option<Partition>)>> {
+    let start = Instant::now();
+    let mut timed = vec![];
+    let mut round_events = vec![];
+    for event in &scenario.events {
+        let partition = scenario.partition(event)?;
+        match (event.at_round, event.at_ms) {
+            (Some(round), _) => round_events.push((round, partition)),
+            (None, Some(ms)) => timed.push((ms, partition)),
+            (None, None) => unreachable!("checked by the scenario"),
+        }
+    }
+    timed.sort_by_key(|(ms, _)| *ms);
+    round_events.sort_by_key(|(round, _)| *round);
+    tokio::spawn(async move {
+        for (ms, partition) in timed {
+            sleep_until(start + Duration::from_millis(ms)).await;
+            set_partition(&emulator, partition);
+        }
+    });
+    Ok(round_events)
+}
+
+pub(crate) fn set_partition(emulator: &Emulator, partition: Option<Partition>) {
+    match &partition {
+        Some(partition) => info!("Partition the network: {}", partition),
+        None => info!("Heal the network"),
+    }
+    emulator.set_partition(partition);
+}
+
+async fn partition(matches: &ArgMatches<'_>) -> Result<()> {
+    let command = match matches.value_of("PARTITION").unwrap() {
+        "heal" => AdminCommand::Partition(None),
+        partition => {
+            partition.parse::<Partition>()?;
+            AdminCommand::Partition(Some(partition.to_string()))
+        }
+    };
+    // Nodes authenticating their peers only take the commands of the members of the committee.
+    let authenticator = match (matches.value_of("key"), matches.value_of("committee")) {
+        (Some(key), Some(path)) => {
+            let committee: Committee = serde_json::from_reader(File::open(path)?)?;
+            Some(Authenticator::new(read_keypair(key)?, move |key| {
+                committee.get_node_id(&Validator::node_public_key(key))
+            }))
+        }
+        (None, None) => None,
+        _ => return Err(anyhow!("--key and --committee go together")),
+    };
+    for address in matches.values_of("ADDRS").unwrap() {
+        let address = address.parse::<SocketAddr>()?;
+        let mut transport = match &authenticator {
+            Some(authenticator) => version::connect_authenticated(address, authenticator).await?,
+            None => version::connect(address).await?,
+        };
+        transport.send(Bytes::from(Message::Admin(command.clone()).to_bytes())).await?;
+        let reply = match transport.next().await {
+            Some(reply) => String::from_utf8_lossy(&reply?).into_owned(),
+            None => "connection closed".to_string(),
+        };
+        if reply != "ok" {
+            return Err(anyhow!("Node at {} refused the command: {}", address, reply));
+        }
+    }
+    Ok(())
+}
+
+async fn reconfigure(matches: &ArgMatches<'_>) -> Result<()> {
+    let epoch = matches.value_of("epoch").unwrap().parse()?;
+    let path = matches.value_of("committee").unwrap();
+    let committee: Committee = serde_json::from_reader(File::open(path)?)?;
+    committee.check_stakes().map_err(|e| anyhow!("Invalid committee {}: {}", path, e))?;
+    let address = matches.value_of("ADDR").unwrap().parse::<SocketAddr>()?;
+
+    let mut reconfiguration = Reconfiguration::new(epoch, committee);
+    for path in matches.value_of("keys").unwrap().split(',') {
+        reconfiguration.sign(&read_keypair(path.trim())?);
+    }
+
+    // Sent like the transactions of the clients.
+    let transaction = reconfiguration.to_transaction();
+    let mut transport = version::connect(address).await?;
+    transport.send(Bytes::from(Message::Transaction(transaction).to_bytes())).await?;
+    info!("Submitted the committee of epoch {} to {}", epoch, address);
+    Ok(())
+}
+
+fn check(matches: &ArgMatches<'_>) -> Result<()> {
+    let max_commit_gap = match matches.value_of("max_commit_gap") {
+        Some(gap) => Some(gap.parse::<u64>()?),
+        None => None,
+    };
+    let logs = matches
+        .values_of("LOGS")
+        .unwrap()
+        .map(NodeLog::read)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for log in &logs {
+        info!("Node {}: {} vertices broadcast, {} vertices committed", log.node_id, log.broadcast.len(), log.committed.len());
+    }
+    let violations = Checker { max_commit_gap }.check(&logs);
+    if violations.is_empty() {
+        info!("No violation found in the {} commit logs", logs.len());
+        return Ok(());
+    }
+    for violation in &violations {
+        warn!("{}", violation);
+    }
+    Err(anyhow!("Found {} violations", violations.len()))
+}
+
 async fn generate(matches: &ArgMatches<'_>) -> Result<()> {
   
   let node_count = matches
@@ -156,6 +581,48 @@ async fn generate(matches: &ArgMatches<'_>) -> Result<()> {
   if faulty_type == 2{
     ftype = 2;
   }       
+
+  // Faulty nodes running a strategy do not drop their messages like `--pretend_failure=1` does.
+  let faulty_args = match matches.value_of("adversary") {
+    Some(strategy) => format!("--pretend_failure=0 --adversary={}", strategy),
+    None => format!("--pretend_failure={}", ftype),
+  };
+  let mut extra_args = match matches.value_of("fault_schedule") {
+    Some(path) => format!(" --fault_schedule={}", path),
+    None => String::new(),
+  };
+  if let Some(path) = matches.value_of("wan") {
+    extra_args.push_str(&format!(" --wan={}", path));
+  }
+  if matches.is_present("encrypt") {
+    extra_args.push_str(" --encrypt");
+  }
+  if let Some(path) = matches.value_of("scenario") {
+    extra_args.push_str(&format!(" --scenario={}", path));
+  }
+  let mut worker_args = String::new();
+  if matches.is_present("encrypt") {
+    worker_args.push_str(" --encrypt");
+  }
+  if matches.is_present("raw_transactions") {
+    extra_args.push_str(" --raw_transactions");
+    worker_args.push_str(" --raw_transactions");
+  }
+  for option in ["max_vertex_blocks", "max_vertex_bytes", "adaptive_batching", "stats_interval"] {
+    if let Some(value) = matches.value_of(option) {
+      extra_args.push_str(&format!(" --{}={}", option, value));
+    }
+  }
+  for option in ["batch_bytes", "batch_delay", "mempool_capacity", "mempool_bytes", "mempool_ttl"] {
+    if let Some(value) = matches.value_of(option) {
+      extra_args.push_str(&format!(" --{}={}", option, value));
+      worker_args.push_str(&format!(" --{}={}", option, value));
+    }
+  }
+  if let Some(mode) = matches.value_of("dissemination") {
+    extra_args.push_str(&format!(" --dissemination={}", mode));
+    worker_args.push_str(&format!(" --dissemination={}", mode));
+  }
   
   if faulty_count > node_count - node_count / 3 * 2 - 1{
       println!("The number of malicious nodes is too high to meet 
@@ -176,11 +643,38 @@ async fn generate(matches: &ArgMatches<'_>) -> Result<()> {
   .unwrap();
 
   // Generate the committee.
-  let committee = Committee::generate(node_count as u32);
+  let (mut committee, keypairs) = match matches.value_of("hosts") {
+    Some(path) => {
+      let hosts: Vec<String> = std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+      if hosts.is_empty() {
+        return Err(anyhow!("No host in {}", path));
+      }
+      Committee::generate_on_hosts(node_count as u32, &hosts)
+    }
+    None => Committee::generate_on_hosts(node_count as u32, &["127.0.0.1".to_string()]),
+  };
+  if let Some(stakes) = matches.value_of("stakes") {
+    for (id, stake) in (1..).zip(stakes.split(',')) {
+      let validator = committee.validators.get_mut(&id).ok_or_else(|| anyhow!("More stakes than nodes"))?;
+      validator.stake = stake.trim().parse()?;
+    }
+    committee.check_stakes().map_err(|e| anyhow!("Invalid stakes {}: {}", stakes, e))?;
+  }
+  let worker_count = matches.value_of("workers").unwrap_or("0").parse::<u32>()?;
+  committee.add_workers(worker_count).map_err(|e| anyhow!("Invalid number of workers {}: {}", worker_count, e))?;
 
   // Save the committee to a JSON file.
   let file = File::create("committee.json")?;
   serde_json::to_writer(file, &committee)?;
+  // Each node authenticates its connections with its own key pair.
+  for (id, keypair) in &keypairs {
+    std::fs::write(format!("node_{}.key", id), keypair)?;
+  }
 
   // Generate a bash script to run the nodes.
   let mut script = File::create("run_nodes.sh")?;
@@ -188,26 +682,38 @@ async fn generate(matches: &ArgMatches<'_>) -> Result<()> {
   for id in 1..=node_count {
     if id==1{
       if id > node_count - faulty_count{
-        writeln!(script, "./node run --id={} --committee=committee.json --batch_size={} --channel_capacity={} --pretend_failure={} &", id, batch_size, channel_capacity, ftype)?;
+        writeln!(script, "./node run --id={} --committee=committee.json --key=node_{}.key --batch_size={} --channel_capacity={} {}{} --commit_log=commits_{}.log --stats=stats_{}.json &", id, id, batch_size, channel_capacity, faulty_args, extra_args, id, id)?;
       }else{
-        writeln!(script, "./node run --id={} --committee=committee.json --batch_size={} --channel_capacity={} --pretend_failure=0 &", id, batch_size, channel_capacity)?;
+        writeln!(script, "./node run --id={} --committee=committee.json --key=node_{}.key --batch_size={} --channel_capacity={} --pretend_failure=0{} --commit_log=commits_{}.log --stats=stats_{}.json &", id, id, batch_size, channel_capacity, extra_args, id, id)?;
       }
     }else{
       if id > node_count - faulty_count{
-        writeln!(script, "./node run --id={} --committee=committee.json --batch_size={} --channel_capacity={} --pretend_failure={} &>/dev/null &", id, batch_size, channel_capacity, ftype)?;
+        writeln!(script, "./node run --id={} --committee=committee.json --key=node_{}.key --batch_size={} --channel_capacity={} {}{} --commit_log=commits_{}.log --stats=stats_{}.json &>/dev/null &", id, id, batch_size, channel_capacity, faulty_args, extra_args, id, id)?;
       }else{
-        writeln!(script, "./node run --id={} --committee=committee.json --batch_size={} --channel_capacity={} --pretend_failure=0 &>/dev/null &", id, batch_size, channel_capacity)?;
+        writeln!(script, "./node run --id={} --committee=committee.json --key=node_{}.key --batch_size={} --channel_capacity={} --pretend_failure=0{} --commit_log=commits_{}.log --stats=stats_{}.json &>/dev/null &", id, id, batch_size, channel_capacity, extra_args, id, id)?;
       }
       //writeln!(script, "./node run --id={} --committee=committee.json --batch_size={} --channel_capacity={} &>/dev/null &", id, batch_size, channel_capacity)?;
     }
     writeln!(script, "THREAD_{}=$!", id-1)?;
   }
+  // The workers take the key of their node.
+  for id in 1..=node_count {
+    for worker in 0..worker_count {
+      writeln!(script, "./node worker --id={} --worker={} --committee=committee.json --key=node_{}.key --batch_size={}{} &>/dev/null &", id, worker, id, batch_size, worker_args)?;
+      writeln!(script, "WORKER_{}_{}=$!", id, worker)?;
+    }
+  }
 
   write!(script, "trap 'kill")?;
 
   for id in 0..=node_count-1 {
     write!(script, " $THREAD_{}", id)?;
   }
+  for id in 1..=node_count {
+    for worker in 0..worker_count {
+      write!(script, " $WORKER_{}_{}", id, worker)?;
+    }
+  }
 
   writeln!(script, "' SIGINT SIGTERM")?;
   writeln!(script, "wait $THREAD_0")?;
@@ -217,9 +723,123 @@ async fn generate(matches: &ArgMatches<'_>) -> Result<()> {
   Ok(())
 }
 
-async fn wait_and_print_vertexs(mut vertex_output_receiver: Receiver<Vertex>) {
-    while let Some(vertex) = vertex_output_receiver.recv().await {
-        info!("Vertex committed: {}", vertex)
+/// Print the committed vertices and forward the vertices created by consensus to the broadcaster,
+/// recording both in the commit log if there is one. With a statistics file, the statistics of the
+/// commits are written to it every interval and when the node is stopped, which ends the loop.
+#[allow(clippy::too_many_arguments)]
+async fn wait_and_print_vertexs(
+    mut vertex_output_receiver: Receiver<(Vertex, Vec<Payload>, CommitPosition)>,
+    mut consensus_to_broadcast_receiver: Receiver<VertexMessage>,
+    vertex_to_broadcast_sender: Sender<VertexMessage>,
+    commit_sender: Sender<(Block, CommitPosition)>,
+    mut batch_committer: Option<BatchCommitter>,
+    mut commit_log: Option<LogWriter>,
+    round_events: Vec<(u64, Option<Partition>)>,
+    emulator: Emulator,
+    node_id: Id,
+    stats: Option<(String, Duration)>,
+) {
+    let mut round_events = round_events.into_iter().peekable();
+    let mut statistics = stats.as_ref().map(|_| Statistics::default());
+    let mut stats_timer = interval(stats.as_ref().map_or(Duration::from_secs(3600), |(_, period)| *period));
+    let stopped = stopped(stats.is_some());
+    tokio::pin!(stopped);
+    // Blocks of the committed batches, given back by the workers, with the time of their commit.
+    let (worker_block_sender, mut worker_block_receiver) = channel::<(Vec<Block>, u64)>(DEFAULT_CHANNEL_CAPACITY);
+    loop {
+        let record = tokio::select! {
+            Some((vertex, payloads, position)) = vertex_output_receiver.recv() => {
+                let now = now_millis();
+                if let Some(statistics) = statistics.as_mut() {
+                    statistics.vertex_committed(position, now);
+                }
+                let (mut transactions, mut batches, mut duplicates) = (0, vec![], 0);
+                for payload in payloads {
+                    match payload {
+                        Payload::Block(block) => {
+                            transactions += block.transactions.len();
+                            if let Some(statistics) = statistics.as_mut() {
+                                statistics.block_committed(&block, now);
+                            }
+                            commit_sender.send((block, position)).await.expect("Failed to send the committed block");
+                        }
+                        Payload::Batch(block_hash) => batches.push(block_hash),
+                        Payload::Duplicate(_) => duplicates += 1,
+                    }
+                }
+                info!(
+                    "Vertex committed: {} with {} transactions, {} batches of the workers and {} blocks already delivered",
+                    vertex, transactions, batches.len(), duplicates
+                );
+                if let (Some(committer), false) = (batch_committer.as_mut(), batches.is_empty()) {
+                    let blocks = committer.commit(batches, position).await;
+                    let worker_block_sender = worker_block_sender.clone();
+                    tokio::spawn(async move {
+                        let _ = worker_block_sender.send((blocks.await, now)).await;
+                    });
+                }
+                Some(LogRecord::Commit(VertexRecord::new(&vertex)))
+            },
+            Some(message) = consensus_to_broadcast_receiver.recv() => {
+                let record = match &message {
+                    VertexMessage::Broadcast(vertex) | VertexMessage::Send(vertex, _) => {
+                        if let Some(statistics) = statistics.as_mut() {
+                            statistics.broadcast(vertex.round(), now_millis());
+                        }
+                        // The partition switches when the node reaches the round, before it sends its vertex.
+                        while let Some((_, partition)) = round_events.next_if(|(round, _)| *round <= vertex.round()) {
+                            set_partition(&emulator, partition);
+                        }
+                        Some(LogRecord::Broadcast(VertexRecord::new(vertex)))
+                    }
+                    VertexMessage::Malformed(..) | VertexMessage::RequestBlock(..) | VertexMessage::SendBlock(..) => None,
+                };
+                vertex_to_broadcast_sender.send(message).await.expect("Failed to send vertex to broadcast");
+                record
+            },
+            Some((blocks, time)) = worker_block_receiver.recv() => {
+                if let Some(statistics) = statistics.as_mut() {
+                    for block in &blocks {
+                        statistics.block_committed(block, time);
+                    }
+                }
+                None
+            },
+            _ = stats_timer.tick(), if stats.is_some() => {
+                write_statistics(&statistics, &stats, node_id);
+                None
+            },
+            _ = &mut stopped => break,
+            else => break,
+        };
+        if let (Some(log), Some(record)) = (commit_log.as_mut(), record) {
+            if let Err(e) = log.write(&record) {
+                warn!("{}", e);
+            }
+        }
+    }
+    write_statistics(&statistics, &stats, node_id);
+}
+
+/// Wait for SIGTERM or SIGINT if `handle`, forever otherwise. Once handled, the signals no longer
+/// kill the process, so they are only handled when the node has statistics to write.
+async fn stopped(handle: bool) {
+    if !handle {
+        return std::future::pending().await;
+    }
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to handle SIGTERM");
+    let mut interrupt = signal(SignalKind::interrupt()).expect("Failed to handle SIGINT");
+    tokio::select! {
+        _ = terminate.recv() => info!("Stopped by SIGTERM"),
+        _ = interrupt.recv() => info!("Stopped by SIGINT"),
+    }
+}
+
+fn write_statistics(statistics: &Option<Statistics>, stats: &Option<(String, Duration)>, node_id: Id) {
+    if let (Some(statistics), Some((path, _))) = (statistics, stats) {
+        if let Err(e) = statistics.write(node_id, path) {
+            warn!("{}", e);
+        }
     }
 }
 
@@ -0,0 +1,148 @@
+use std::fs;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use bytes::{BufMut as _, BytesMut};
+use rand::Rng;
+
+use model::transaction::SAMPLE_HEADER_SIZE;
+
+/// Distribution of the sizes of the transactions, in bytes.
+#[derive(Clone, Debug)]
+pub enum SizeDistribution {
+    Fixed(usize),
+    Uniform { min: usize, max: usize },
+    /// Sizes from `min` up, the `k`-th one drawn with a probability in `1 / k^exponent`, so most
+    /// transactions are small and a few are large. Holds the cumulative probabilities.
+    Zipf { min: usize, cdf: Vec<f64> },
+}
+
+impl SizeDistribution {
+    /// Parse `fixed`, `uniform` or `zipf`, for sizes between `min` and `max`.
+    pub fn parse(name: &str, min: usize, max: usize, exponent: f64) -> Result<Self> {
+        let min = min.min(max);
+        match name {
+            "fixed" => Ok(Self::Fixed(max)),
+            "uniform" => Ok(Self::Uniform { min, max }),
+            "zipf" => {
+                let weights: Vec<f64> = (1..=max - min + 1).map(|k| (k as f64).powf(-exponent)).collect();
+                let total: f64 = weights.iter().sum();
+                let cdf = weights
+                    .iter()
+                    .scan(0.0, |sum, weight| {
+                        *sum += weight / total;
+                        Some(*sum)
+                    })
+                    .collect();
+                Ok(Self::Zipf { min, cdf })
+            }
+            _ => Err(anyhow!("Unknown size distribution {}, expected fixed, uniform or zipf", name)),
+        }
+    }
+
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> usize {
+        match self {
+            Self::Fixed(size) => *size,
+            Self::Uniform { min, max } => rng.gen_range(*min..=*max),
+            Self::Zipf { min, cdf } => {
+                let u: f64 = rng.gen();
+                min + cdf.partition_point(|p| *p < u).min(cdf.len() - 1)
+            }
+        }
+    }
+}
+
+/// What the transactions are padded with after their header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Content {
+    Zeros,
+    Random,
+    /// Runs of repeated random bytes, two in every 16 bytes, which compress well.
+    Compressible,
+}
+
+impl FromStr for Content {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "zeros" => Ok(Self::Zeros),
+            "random" => Ok(Self::Random),
+            "compressible" => Ok(Self::Compressible),
+            _ => Err(anyhow!("Unknown content {}, expected zeros, random or compressible", name)),
+        }
+    }
+}
+
+/// Write the payload of sample transaction `counter` into `payload`, stamped with the current time
+/// so the nodes measure its latency, and padded to `size` bytes, or to its header if it does not fit.
+pub fn fill<R: Rng>(payload: &mut BytesMut, counter: u64, size: usize, content: Content, rng: &mut R) {
+    payload.put_u8(0u8); // Sample txs start with 0.
+    payload.put_u64(counter); // This counter identifies the tx.
+    payload.put_u64(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64));
+    let start = payload.len();
+    payload.resize(start + size.saturating_sub(SAMPLE_HEADER_SIZE), 0u8);
+    let padding = &mut payload[start..];
+    match content {
+        Content::Zeros => {}
+        Content::Random => rng.fill(padding),
+        Content::Compressible => {
+            for run in padding.chunks_mut(16) {
+                let length = rng.gen_range(1..=run.len());
+                run[..length].fill(rng.gen());
+                run[length..].fill(rng.gen());
+            }
+        }
+    }
+}
+
+/// Read a trace of transactions to replay: one per line, with the time in milliseconds from the
+/// start at which to send it and its size in bytes. Blank lines and lines starting with `#` are
+/// skipped.
+pub fn read_trace(path: &str) -> Result<Vec<(Duration, usize)>> {
+    let file = fs::read_to_string(path).context(format!("Failed to read the trace {}", path))?;
+    let mut trace = file
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(number, line)| {
+            let mut fields = line.split_whitespace();
+            let parsed = fields.next().zip(fields.next()).and_then(|(time, size)| {
+                Some((Duration::from_millis(time.parse().ok()?), size.parse().ok()?))
+            });
+            parsed.ok_or_else(|| anyhow!("Invalid line {} of the trace {}, expected a time and a size", number + 1, path))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    trace.sort_by_key(|(time, _)| *time);
+    Ok(trace)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng as _;
+
+    use super::*;
+
+    #[test]
+    fn test_payloads() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let zipf = SizeDistribution::parse("zipf", 10, 1000, 1.0).unwrap();
+        let sizes: Vec<usize> = (0..1000).map(|_| zipf.sample(&mut rng)).collect();
+        assert!(sizes.iter().all(|size| (10..=1000).contains(size)));
+        assert!(sizes.iter().filter(|size| **size < 20).count() > sizes.len() / 3);
+
+        for content in [Content::Zeros, Content::Random, Content::Compressible] {
+            let mut payload = BytesMut::new();
+            fill(&mut payload, 7, 100, content, &mut rng);
+            assert_eq!(payload.len(), 100);
+            assert_eq!(&payload[..9], &[0, 0, 0, 0, 0, 0, 0, 0, 7]);
+            assert!(model::transaction::sample_send_time(&payload).is_some());
+        }
+        // The header is never cut.
+        let mut payload = BytesMut::new();
+        fill(&mut payload, 7, 3, Content::Zeros, &mut rng);
+        assert_eq!(payload.len(), SAMPLE_HEADER_SIZE);
+    }
+}
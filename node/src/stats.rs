@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use consensus::MAX_WAVE;
+use model::block::Block;
+use model::committee::Id;
+use model::transaction::{sample_send_time, CommitPosition};
+use model::{Round, Wave};
+
+/// Current time in ms since the Unix epoch, the clock of the send times of the clients.
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Statistics of the transactions committed by a node: throughput between the first and the last
+/// commit, latency of the sample transactions from the time their client sent them, which assumes
+/// the clocks of the clients and nodes are synchronized, and delay of the commit of every wave from
+/// the time the node broadcast its vertex in the first round of the wave.
+#[derive(Debug, Default)]
+pub struct Statistics {
+    transactions: u64,
+    bytes: u64,
+    first_commit: Option<u64>,
+    last_commit: u64,
+    /// Latencies of the sample transactions in ms.
+    latencies: Vec<u64>,
+    wave_starts: BTreeMap<Wave, u64>,
+    wave_delays: BTreeMap<Wave, u64>,
+}
+
+/// What the statistics are written as.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub node: Id,
+    pub time: u64,
+    pub duration_ms: u64,
+    pub transactions: u64,
+    pub bytes: u64,
+    pub transactions_per_second: f64,
+    pub bytes_per_second: f64,
+    pub samples: usize,
+    pub latency_p50_ms: Option<u64>,
+    pub latency_p90_ms: Option<u64>,
+    pub latency_p99_ms: Option<u64>,
+    pub wave_commit_delays_ms: BTreeMap<Wave, u64>,
+}
+
+impl Statistics {
+    /// Account for a vertex of the node, broadcast at `now`.
+    pub fn broadcast(&mut self, round: Round, now: u64) {
+        if round % MAX_WAVE == 1 {
+            self.wave_starts.entry(round / MAX_WAVE + 1).or_insert(now);
+        }
+    }
+
+    /// Account for a vertex committed at `now`, before the blocks it delivers.
+    pub fn vertex_committed(&mut self, position: CommitPosition, now: u64) {
+        if !self.wave_delays.contains_key(&position.wave) {
+            if let Some(start) = self.wave_starts.get(&position.wave) {
+                self.wave_delays.insert(position.wave, now.saturating_sub(*start));
+            }
+            self.wave_starts = self.wave_starts.split_off(&(position.wave + 1));
+        }
+    }
+
+    pub fn block_committed(&mut self, block: &Block, now: u64) {
+        self.first_commit.get_or_insert(now);
+        self.last_commit = now;
+        self.transactions += block.transactions.len() as u64;
+        for transaction in &block.transactions {
+            self.bytes += transaction.len() as u64;
+            if let Some(sent) = sample_send_time(transaction) {
+                self.latencies.push(now.saturating_sub(sent));
+            }
+        }
+    }
+
+    pub fn summary(&self, node: Id) -> Summary {
+        let duration_ms = self.first_commit.map_or(0, |first| self.last_commit - first);
+        let per_second = |count: u64| match duration_ms {
+            0 => 0.0,
+            _ => count as f64 * 1000.0 / duration_ms as f64,
+        };
+        let mut latencies = self.latencies.clone();
+        latencies.sort_unstable();
+        let percentile = |p: usize| latencies.get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1))).copied();
+        Summary {
+            node,
+            time: now_millis(),
+            duration_ms,
+            transactions: self.transactions,
+            bytes: self.bytes,
+            transactions_per_second: per_second(self.transactions),
+            bytes_per_second: per_second(self.bytes),
+            samples: latencies.len(),
+            latency_p50_ms: percentile(50),
+            latency_p90_ms: percentile(90),
+            latency_p99_ms: percentile(99),
+            wave_commit_delays_ms: self.wave_delays.clone(),
+        }
+    }
+
+    /// Write the summary to `path` as JSON, replacing the previous one.
+    pub fn write(&self, node: Id, path: &str) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.summary(node))?;
+        fs::write(path, json).context(format!("Failed to write the statistics to {}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statistics() {
+        let mut statistics = Statistics::default();
+        statistics.broadcast(1, 1_000);
+        statistics.broadcast(2, 1_100);
+        statistics.broadcast(5, 1_400);
+
+        let sample = |sent: u64| [&[0][..], &0u64.to_be_bytes(), &sent.to_be_bytes(), &[0; 3]].concat();
+        let block = Block::new((0..100).map(|i| sample(900 + i)).chain([vec![1; 20]]).collect());
+        statistics.vertex_committed(CommitPosition { wave: 1, sequence: 0 }, 2_000);
+        statistics.block_committed(&block, 2_000);
+        statistics.vertex_committed(CommitPosition { wave: 1, sequence: 1 }, 3_000);
+        statistics.block_committed(&Block::new(vec![sample(1_900)]), 3_000);
+
+        let summary = statistics.summary(1);
+        assert_eq!(summary.transactions, 102);
+        assert_eq!(summary.bytes, 101 * 20 + 20);
+        assert_eq!(summary.transactions_per_second, 102.0);
+        assert_eq!(summary.samples, 101);
+        assert_eq!(summary.latency_p50_ms, Some(1_051));
+        assert_eq!(summary.latency_p99_ms, Some(1_100));
+        // The delay of a wave is taken at its first commit.
+        assert_eq!(summary.wave_commit_delays_ms, BTreeMap::from([(1, 1_000)]));
+        assert_eq!(statistics.wave_starts, BTreeMap::from([(2, 1_400)]));
+    }
+}
@@ -0,0 +1,18 @@
+use checker::Violation;
+use model::committee::Id;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SimulationError {
+    #[error("Seed {0}: {1}")]
+    Violation(u64, Violation),
+
+    #[error("Seed {0}: node {1} committed {2} vertices, expected at least {3}")]
+    NotEnoughCommits(u64, Id, usize, usize),
+
+    #[error("Unknown adversary strategy {0}")]
+    UnknownAdversary(String),
+
+    #[error("Invalid fault schedule: {0}")]
+    InvalidFaultSchedule(String),
+}
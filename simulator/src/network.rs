@@ -0,0 +1,139 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use model::committee::Id;
+use network::Partition;
+
+/// Virtual time of the simulation, in ticks.
+pub type Time = u64;
+
+/// Parameters of the virtual links between the nodes.
+#[derive(Clone, Debug)]
+pub struct NetworkConfig {
+    /// Minimum delay of a message on a link (in ticks).
+    pub min_delay: Time,
+    /// Maximum delay of a message on a link (in ticks). Delays are drawn uniformly between
+    /// `min_delay` and `max_delay`, which reorders messages sent close to each other.
+    pub max_delay: Time,
+    /// Probability that a single transmission of a message is lost.
+    pub drop_probability: f64,
+    /// Delay before a lost message is transmitted again, as `ReliableSender` does.
+    pub retry_delay: Time,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            min_delay: 1,
+            max_delay: 10,
+            drop_probability: 0.0,
+            retry_delay: 20,
+        }
+    }
+}
+
+/// A message waiting in the virtual network to be delivered.
+struct InFlight<T> {
+    deliver_at: Time,
+    /// Sequence number breaking the ties between messages delivered at the same time.
+    sequence: u64,
+    to: Id,
+    message: T,
+}
+
+impl<T> PartialEq for InFlight<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.deliver_at == other.deliver_at && self.sequence == other.sequence
+    }
+}
+
+impl<T> Eq for InFlight<T> {}
+
+impl<T> PartialOrd for InFlight<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for InFlight<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed so that the `BinaryHeap` pops the earliest message first.
+        (other.deliver_at, other.sequence).cmp(&(self.deliver_at, self.sequence))
+    }
+}
+
+/// Network connecting the simulated nodes. Every random decision (delays and drops) is taken from
+/// the RNG given by the caller, so the whole schedule is determined by the simulation seed.
+pub struct VirtualNetwork<T> {
+    config: NetworkConfig,
+    queue: BinaryHeap<InFlight<T>>,
+    sequence: u64,
+    /// Number of transmissions lost so far.
+    dropped: usize,
+    partition: Option<Partition>,
+    /// Messages sent across the partition, in order, with their sender and destination.
+    held: Vec<(Id, Id, T)>,
+}
+
+impl<T> VirtualNetwork<T> {
+    pub fn new(config: NetworkConfig) -> Self {
+        Self {
+            config,
+            queue: BinaryHeap::new(),
+            sequence: 0,
+            dropped: 0,
+            partition: None,
+            held: vec![],
+        }
+    }
+
+    /// Send a message over a link. Lost transmissions are retried until one of them gets through, and
+    /// messages cut by the partition are held until it heals, as `ReliableSender` keeps retrying.
+    pub fn send(&mut self, rng: &mut StdRng, now: Time, from: Id, to: Id, message: T) {
+        if self.partition.as_ref().is_some_and(|partition| partition.separates(from, to)) {
+            self.held.push((from, to, message));
+            return;
+        }
+        let mut delay = 0;
+        while self.config.drop_probability > 0.0 && rng.gen_bool(self.config.drop_probability) {
+            delay += self.config.retry_delay;
+            self.dropped += 1;
+        }
+        delay += rng.gen_range(self.config.min_delay..=self.config.max_delay);
+        self.schedule(now + delay, to, message);
+    }
+
+    /// Switch to another partition, or heal the network. Held messages which are not cut anymore are
+    /// sent now; messages already in flight are delivered whatever the partition.
+    pub fn set_partition(&mut self, rng: &mut StdRng, now: Time, partition: Option<Partition>) {
+        self.partition = partition;
+        for (from, to, message) in std::mem::take(&mut self.held) {
+            self.send(rng, now, from, to, message);
+        }
+    }
+
+    /// Deliver a message at a given time, bypassing the link model.
+    pub fn schedule(&mut self, deliver_at: Time, to: Id, message: T) {
+        self.sequence += 1;
+        self.queue.push(InFlight {
+            deliver_at,
+            sequence: self.sequence,
+            to,
+            message,
+        });
+    }
+
+    /// Take the next message to deliver together with its delivery time and destination.
+    pub fn pop(&mut self) -> Option<(Time, Id, T)> {
+        self.queue
+            .pop()
+            .map(|m| (m.deliver_at, m.to, m.message))
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, HashMap};
+
+use log::{debug, info};
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+
+use checker::check_prefix_consistency;
+use consensus::{parse_adversary, parse_schedule, Action, ConsensusCore, Event, FaultPeriod};
+use model::block::{Block, BlockHash};
+use model::committee::{Committee, Id, Stake};
+use model::epoch::Reconfiguration;
+use model::vertex::{Vertex, VertexHash};
+use model::Round;
+use network::Partition;
+
+use crate::error::SimulationError;
+use crate::network::{NetworkConfig, Time, VirtualNetwork};
+
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    /// Number of nodes in the committee.
+    pub node_count: u32,
+    /// Seed of every random decision of the run (committee keys, delays, drops and reordering).
+    pub seed: u64,
+    pub network: NetworkConfig,
+    /// Interval between two blocks handed to the consensus of every node (in ticks).
+    pub block_interval: Time,
+    /// Virtual time at which the simulation stops.
+    pub duration: Time,
+    /// Faulty type of the nodes, with the same meaning as `--pretend_failure`. Missing nodes are correct.
+    pub faulty_nodes: HashMap<Id, usize>,
+    /// Byzantine strategy of the nodes, with the same syntax as `--adversary`. It overrides the faulty type.
+    pub adversaries: HashMap<Id, String>,
+    /// Strategies of the nodes during ranges of rounds, with the same format as `--fault_schedule`.
+    pub fault_schedule: HashMap<Id, Vec<FaultPeriod>>,
+    /// Partitions the network switches to at given times, `None` healing it, like a `--scenario` does.
+    pub partitions: Vec<(Time, Option<Partition>)>,
+    /// Reconfiguration transactions handed to every node at given times, like `reconfigure` does.
+    pub reconfigurations: Vec<(Time, Reconfiguration)>,
+    /// Stake of the validators, 1 for the missing ones.
+    pub stakes: HashMap<Id, Stake>,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            node_count: 4,
+            seed: 0,
+            network: NetworkConfig::default(),
+            block_interval: 5,
+            duration: 400,
+            faulty_nodes: HashMap::new(),
+            adversaries: HashMap::new(),
+            fault_schedule: HashMap::new(),
+            partitions: vec![],
+            reconfigurations: vec![],
+            stakes: HashMap::new(),
+        }
+    }
+}
+
+/// Messages travelling through the virtual network.
+#[derive(Clone)]
+enum Message {
+    Vertex(Vertex),
+    /// Block handed to the node to propose it.
+    Block(Block),
+    /// Block of another node, sent along with the blocks it proposes.
+    BlockCopy(Block),
+    /// Block sent back to the node which requested it.
+    BlockReply(Block),
+    /// Request of the given node for a block it is missing.
+    BlockRequest(BlockHash, Id),
+}
+
+/// A consensus state machine together with what it committed so far.
+struct SimulatedNode {
+    consensus: ConsensusCore,
+    committed: Vec<VertexHash>,
+    commit_times: Vec<Time>,
+}
+
+/// Runs several `ConsensusCore` instances in the same process over a `VirtualNetwork`. The nodes are
+/// driven one message at a time, so a run is entirely determined by its configuration and seed.
+pub struct Simulation {
+    config: SimulationConfig,
+    rng: StdRng,
+    network: VirtualNetwork<Message>,
+    nodes: BTreeMap<Id, SimulatedNode>,
+}
+
+impl Simulation {
+    pub fn new(config: SimulationConfig) -> Result<Self, SimulationError> {
+        let mut committee = Committee::generate_from_seed(config.node_count, config.seed);
+        for (id, stake) in &config.stakes {
+            if let Some(validator) = committee.validators.get_mut(id) {
+                validator.stake = *stake;
+            }
+        }
+        let nodes = (1..=config.node_count)
+            .map(|id| {
+                let strategy = match (config.adversaries.get(&id), config.faulty_nodes.get(&id)) {
+                    (Some(strategy), _) => strategy.as_str(),
+                    (None, Some(1)) => "drop",
+                    (None, Some(2)) => "wave_withholding",
+                    (None, _) => "honest",
+                };
+                let periods = config.fault_schedule.get(&id).map(Vec::as_slice).unwrap_or_default();
+                Ok((id, Self::create_node(id, committee.clone(), strategy, periods)?))
+            })
+            .collect::<Result<_, SimulationError>>()?;
+        Ok(Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            network: VirtualNetwork::new(config.network.clone()),
+            config,
+            nodes,
+        })
+    }
+
+    fn create_node(
+        node_id: Id,
+        committee: Committee,
+        strategy: &str,
+        periods: &[FaultPeriod],
+    ) -> Result<SimulatedNode, SimulationError> {
+        let mut adversary = parse_adversary(strategy, node_id, &committee)
+            .ok_or_else(|| SimulationError::UnknownAdversary(strategy.to_string()))?;
+        if !periods.is_empty() {
+            adversary = parse_schedule(periods, adversary, node_id, &committee)
+                .map_err(SimulationError::InvalidFaultSchedule)?;
+        }
+        Ok(SimulatedNode {
+            consensus: ConsensusCore::new(node_id, committee, adversary),
+            committed: vec![],
+            commit_times: vec![],
+        })
+    }
+
+    /// Run the simulation until `duration` and report what every node committed.
+    pub fn run(&mut self) -> SimulationReport {
+        info!("Start simulation of {} nodes with seed {}", self.config.node_count, self.config.seed);
+        self.schedule_blocks();
+
+        let mut partitions = self.config.partitions.clone();
+        partitions.sort_by_key(|(at, _)| *at);
+        let mut partitions = partitions.into_iter().peekable();
+        while let Some((now, to, message)) = self.network.pop() {
+            if now > self.config.duration {
+                break;
+            }
+            while let Some((at, partition)) = partitions.next_if(|(at, _)| *at <= now) {
+                info!("Switch to partition {:?} at {}", partition.as_ref().map(Partition::to_string), at);
+                self.network.set_partition(&mut self.rng, at, partition);
+            }
+            self.deliver(now, to, message);
+        }
+
+        let report = SimulationReport {
+            seed: self.config.seed,
+            commits: self.nodes.iter().map(|(id, node)| (*id, node.committed.clone())).collect(),
+            commit_times: self.nodes.iter().map(|(id, node)| (*id, node.commit_times.clone())).collect(),
+            rounds: self.nodes.iter().map(|(id, node)| (*id, node.consensus.current_round())).collect(),
+            dropped_messages: self.network.dropped(),
+        };
+        info!("Simulation with seed {} finished: {:?}", self.config.seed, report.rounds);
+        report
+    }
+
+    /// Hand a new block to every node each `block_interval`, the way `BlockBuilder` feeds `Consensus`.
+    fn schedule_blocks(&mut self) {
+        let ids: Vec<Id> = self.nodes.keys().copied().collect();
+        for (counter, at) in (0..=self.config.duration).step_by(self.config.block_interval as usize).enumerate() {
+            for id in &ids {
+                let transaction = [id.to_be_bytes().to_vec(), (counter as u64).to_be_bytes().to_vec()].concat();
+                self.network.schedule(at, *id, Message::Block(Block::new(vec![transaction])));
+            }
+        }
+        for (at, reconfiguration) in &self.config.reconfigurations {
+            for id in &ids {
+                let block = Block::new(vec![reconfiguration.to_transaction()]);
+                self.network.schedule(*at, *id, Message::Block(block));
+            }
+        }
+    }
+
+    fn deliver(&mut self, now: Time, to: Id, message: Message) {
+        let node = self.nodes.get_mut(&to).unwrap();
+        let event = match message.clone() {
+            // Nodes pretending a failure drop everything they receive, like `NodeHandler` does, except
+            // that the core still sees the rounds of the vertices to end its drop periods.
+            Message::Vertex(vertex) => Some(Event::VertexReceived(vertex)),
+            _ if node.consensus.drops_packets() => None,
+            Message::Block(block) => Some(Event::BlockAvailable(block)),
+            Message::BlockCopy(block) => Some(Event::BlockReceived(block)),
+            Message::BlockReply(block) => Some(Event::BlockReplied(block)),
+            Message::BlockRequest(block_hash, id) => Some(Event::BlockRequested(block_hash, id)),
+        };
+
+        let mut to_send = vec![];
+        // `BlockBuilder` sends its blocks to every node, ahead of the vertices proposing them.
+        if let Message::Block(block) = &message {
+            let others = self.nodes.keys().copied().filter(|id| *id != to).collect();
+            to_send.push((Message::BlockCopy(block.clone()), others));
+        }
+        let node = self.nodes.get_mut(&to).unwrap();
+        let actions = event.map_or_else(Vec::new, |event| node.consensus.handle(event));
+        for action in actions {
+            match action {
+                Action::OutputCommit(vertex, ..) => {
+                    debug!("Vertex committed by node {} at {}: {}", to, now, vertex);
+                    node.committed.push(vertex.hash());
+                    node.commit_times.push(now);
+                }
+                // `VertexBroadcaster` sends every vertex to the committee of its round, including its author.
+                Action::BroadcastVertex(vertex) => {
+                    let mut ids: Vec<Id> = node.consensus.epochs().committee_at(vertex.round()).validators.keys().copied().collect();
+                    // In a fixed order, so that the delays are drawn the same way in every run.
+                    ids.sort_unstable();
+                    to_send.push((Message::Vertex(vertex), ids))
+                }
+                Action::SendVertex(vertex, recipients) => to_send.push((Message::Vertex(vertex), recipients)),
+                Action::RequestBlock(block_hash, owner) => to_send.push((Message::BlockRequest(block_hash, to), vec![owner])),
+                Action::SendBlock(block, id) => to_send.push((Message::BlockReply(block), vec![id])),
+                // The receivers fail to decode them and drop them before they reach consensus.
+                Action::SendMalformed(..) => {}
+            }
+        }
+
+        for (message, recipients) in to_send {
+            for id in recipients {
+                self.network.send(&mut self.rng, now, to, id, message.clone());
+            }
+        }
+    }
+}
+
+/// Outcome of a simulation run.
+#[derive(Clone, Debug)]
+pub struct SimulationReport {
+    /// Seed the run was started from, it replays the exact same run.
+    pub seed: u64,
+    /// Hashes of the vertices committed by every node, in commit order.
+    pub commits: BTreeMap<Id, Vec<VertexHash>>,
+    /// Virtual time of each of these commits.
+    pub commit_times: BTreeMap<Id, Vec<Time>>,
+    /// Round reached by every node at the end of the run.
+    pub rounds: BTreeMap<Id, Round>,
+    /// Number of transmissions lost by the virtual network.
+    pub dropped_messages: usize,
+}
+
+impl SimulationReport {
+    /// Check that the sequences committed by the given nodes are prefixes of one another.
+    pub fn check_agreement(&self, nodes: &[Id]) -> Result<(), SimulationError> {
+        let sequences: BTreeMap<_, _> = nodes.iter().map(|id| (*id, self.commits[id].clone())).collect();
+        check_prefix_consistency(&sequences).map_err(|e| SimulationError::Violation(self.seed, e))
+    }
+
+    /// Check that each of the given nodes committed at least `min_commits` vertices.
+    pub fn check_liveness(&self, nodes: &[Id], min_commits: usize) -> Result<(), SimulationError> {
+        for id in nodes {
+            let committed = self.commits[id].len();
+            if committed < min_commits {
+                return Err(SimulationError::NotEnoughCommits(self.seed, *id, committed, min_commits));
+            }
+        }
+        Ok(())
+    }
+}
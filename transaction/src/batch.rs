@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use model::block::BlockHash;
+
+/// Number of commit latencies measured between two changes of the adaptive batch size.
+const ADJUST_EVERY: usize = 8;
+/// Blocks not committed within this time are no longer waited for.
+const PENDING_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// When a `BlockBuilder` seals the transactions it has into a block: as soon as any of the limits
+/// is reached, so transactions do not wait for companions at low load.
+#[derive(Clone, Copy, Debug)]
+pub struct BatchConfig {
+    /// Number of transactions of a block. In adaptive mode, the largest number it grows to.
+    pub max_transactions: usize,
+    /// Total size of the transactions of a block.
+    pub max_bytes: usize,
+    /// Longest time the first transaction of a block waits for the block to be sealed.
+    pub max_delay: Duration,
+    /// Commit latency of its blocks the builder aims at by adapting the number of transactions of
+    /// the blocks, if any. Only the builder of a node sees its blocks committed, so the workers
+    /// ignore it.
+    pub target_latency: Option<Duration>,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_transactions: 10,
+            max_bytes: 500_000,
+            max_delay: Duration::from_millis(100),
+            target_latency: None,
+        }
+    }
+}
+
+/// Number of transactions of the blocks in adaptive mode, from the time the blocks take to be
+/// committed. When they take longer than the target, the consensus falls behind the blocks and
+/// larger ones amortize its cost, so the size doubles. When they take less than half the target,
+/// the size halves so blocks are sealed sooner.
+#[derive(Debug)]
+pub(crate) struct AdaptiveBatchSize {
+    target: Duration,
+    max_transactions: usize,
+    size: usize,
+    /// Moving average of the commit latency.
+    latency: Option<Duration>,
+    samples: usize,
+    sealed: HashMap<BlockHash, Instant>,
+}
+
+impl AdaptiveBatchSize {
+    pub(crate) fn new(target: Duration, max_transactions: usize) -> Self {
+        Self { target, max_transactions: max_transactions.max(1), size: 1, latency: None, samples: 0, sealed: HashMap::new() }
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    pub(crate) fn sealed(&mut self, block_hash: BlockHash, now: Instant) {
+        self.sealed.retain(|_, time| now.duration_since(*time) < PENDING_TIMEOUT);
+        self.sealed.insert(block_hash, now);
+    }
+
+    /// Account for a committed block, which is ignored if it was not sealed by this builder.
+    pub(crate) fn committed(&mut self, block_hash: &BlockHash, now: Instant) {
+        let sample = match self.sealed.remove(block_hash) {
+            Some(time) => now.duration_since(time),
+            None => return,
+        };
+        let latency = match self.latency {
+            Some(latency) => (latency * 4 + sample) / 5,
+            None => sample,
+        };
+        self.latency = Some(latency);
+        self.samples += 1;
+        if self.samples < ADJUST_EVERY {
+            return;
+        }
+        self.samples = 0;
+        if latency > self.target {
+            self.size = (self.size * 2).min(self.max_transactions);
+        } else if latency < self.target / 2 {
+            self.size = (self.size / 2).max(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adaptive_batch_size() {
+        let mut adaptive = AdaptiveBatchSize::new(Duration::from_millis(100), 6);
+        let start = Instant::now();
+        let commit = |adaptive: &mut AdaptiveBatchSize, latency: u64| {
+            for i in 0..ADJUST_EVERY as u8 {
+                adaptive.sealed([i; 32], start);
+                adaptive.committed(&[i; 32], start + Duration::from_millis(latency));
+            }
+        };
+
+        // Slow commits grow the blocks, up to the limit.
+        commit(&mut adaptive, 300);
+        assert_eq!(adaptive.size(), 2);
+        commit(&mut adaptive, 300);
+        commit(&mut adaptive, 300);
+        assert_eq!(adaptive.size(), 6);
+
+        // Fast commits shrink them once the average follows.
+        commit(&mut adaptive, 80);
+        assert_eq!(adaptive.size(), 6);
+        commit(&mut adaptive, 10);
+        commit(&mut adaptive, 10);
+        assert!(adaptive.size() < 6);
+
+        // The blocks of the other builders are ignored.
+        adaptive.committed(&[42; 32], start);
+        assert_eq!(adaptive.samples, 0);
+    }
+}
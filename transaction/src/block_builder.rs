@@ -1,36 +1,120 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
 use bytes::Bytes;
-use log::{debug, error, info};
-use tokio::sync::mpsc::{Receiver};
+use log::{debug, error, info, warn};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::watch;
+use tokio::time::{sleep, sleep_until, Instant};
+
+use model::block::{transaction_hash, Block, Transaction};
+use model::chunk::{shard_counts, Chunk};
+use model::committee::{Committee, Id, WorkerId};
+use model::epoch::Epochs;
+use model::message::Message;
+use model::transaction::{CommitPosition, Receipt};
+use network::{Authenticator, CancelHandler, Emulator, ReliableSender, Replier};
 
-use model::block::{Block, Transaction};
-use model::committee::Committee;
-use network::ReliableSender;
+use crate::batch::{AdaptiveBatchSize, BatchConfig};
+use crate::mempool::{Mempool, MempoolConfig};
 
-use crate::transaction_coordinator::BlockMessage;
+/// Where a `BlockBuilder` sends the blocks it seals.
+#[derive(Clone, Copy, Debug)]
+pub enum Destination {
+    /// Every other node, which stores them, the given node of the builder proposing them.
+    Nodes(Id),
+    /// The workers of the given index of every node, which store them, the node of the builder
+    /// being told to propose them.
+    Workers(Id, WorkerId),
+}
 
-const BATCH_SIZE: usize = 10;
+impl Destination {
+    /// Receivers of the blocks in the committee, by increasing id of their node, which gives the
+    /// index of the chunk each of them receives.
+    pub(crate) fn receivers(&self, committee: &Committee) -> Vec<(Id, SocketAddr)> {
+        committee
+            .get_node_ids()
+            .into_iter()
+            .filter_map(|id| match self {
+                Destination::Nodes(_) => committee.get_node_address(id).map(|address| (id, address)),
+                Destination::Workers(_, worker) => committee.get_worker_address(id, *worker).map(|address| (id, address)),
+            })
+            .collect()
+    }
+}
+
+/// How a `BlockBuilder` sends the blocks it seals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dissemination {
+    /// The whole block to every receiver, so the builder sends `n` times the block.
+    Full,
+    /// One erasure-coded chunk to every receiver of the current committee, which echoes it to the
+    /// others, so the builder sends about `n / (2f + 1)` times the block. The receivers which only
+    /// join in the next epochs fetch the blocks they miss from the owners of the vertices.
+    ErasureCoded,
+}
+
+impl FromStr for Dissemination {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Dissemination::Full),
+            "erasure" => Ok(Dissemination::ErasureCoded),
+            _ => Err(format!("Unknown dissemination '{}', expected 'full' or 'erasure'", s)),
+        }
+    }
+}
 
 pub struct BlockBuilder {
-    committee: Committee,
-    transaction_receiver: Receiver<Transaction>,
+    /// Blocks go to the current committee and to those of the next epochs, which propose later.
+    epochs: watch::Receiver<Epochs>,
+    /// Transactions of the clients, with the connection to send their receipts on.
+    transaction_receiver: Receiver<(Transaction, Replier)>,
     current_transactions: Vec<Transaction>,
+    current_bytes: usize,
     network: ReliableSender,
-    batch_size: usize,
+    /// Node of the builder, which gets the blocks to propose when the builder runs in it.
+    own_block_sender: Option<Sender<Block>>,
+    config: BatchConfig,
+    adaptive: Option<AdaptiveBatchSize>,
+    mempool: Mempool,
+    /// Committed blocks, which leave the mempool, are notified to the clients and give the commit
+    /// latency in adaptive mode. Without them, transactions leave the mempool once sealed into a block.
+    commit_receiver: Option<Receiver<(Block, CommitPosition)>>,
+    destination: Destination,
+    dissemination: Dissemination,
 }
 
 impl BlockBuilder {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
-        transaction_receiver: Receiver<Transaction>,
-        committee: Committee,
-        batch_size: usize,
+        transaction_receiver: Receiver<(Transaction, Replier)>,
+        epochs: watch::Receiver<Epochs>,
+        own_block_sender: Option<Sender<Block>>,
+        config: BatchConfig,
+        mempool: MempoolConfig,
+        commit_receiver: Option<Receiver<(Block, CommitPosition)>>,
+        destination: Destination,
+        dissemination: Dissemination,
+        emulator: Emulator,
+        authenticator: Option<Authenticator>,
     ) {
         tokio::spawn(async move {
             Self {
-                committee,
+                epochs,
                 transaction_receiver,
                 current_transactions: vec![],
-                network: ReliableSender::new(),
-                batch_size,
+                current_bytes: 0,
+                network: ReliableSender::with_emulator(emulator).with_authenticator(authenticator),
+                own_block_sender,
+                config,
+                adaptive: config.target_latency.map(|target| AdaptiveBatchSize::new(target, config.max_transactions)),
+                mempool: Mempool::new(mempool),
+                commit_receiver,
+                destination,
+                dissemination,
             }
                 .run()
                 .await;
@@ -38,35 +122,183 @@ impl BlockBuilder {
     }
 
     async fn run(&mut self) {
-        while let Some(transaction) = self.transaction_receiver.recv().await {
-            debug!("BlockBuilder received transaction {:?}", transaction);
-            self.current_transactions.push(transaction);
-
-            if self.current_transactions.len() >= self.batch_size {
-                info!("BlockBuilder has enough transactions to make a block. Broadcast it to others");
-                let message = BlockMessage::Block(Block::new(self.current_transactions.drain(..).collect()));
-                let serialized = bincode::serialize(&message).expect("Failed to serialize the block");
-
-                // Broadcast the block through the network.
-                let bytes = Bytes::from(serialized.clone());
-                debug!("Broadcast Start!");
-                let handlers = self.network.broadcast(self.committee.get_block_receiver_addresses(), bytes).await;
-                
-                // for h in handlers {
-                //     if let Err(e) = h.await {
-                //         error!("Broadcast of the block was not successful: {:?}", e);
-                //     }
-                // }
-                // debug!("Broadcast has finished!");
-                tokio::spawn(async move {
-                  for h in handlers {
-                      if let Err(e) = h.await {
-                          error!("Broadcast of the block was not successful: {:?}", e);
-                      }
-                  }
-                  debug!("Broadcast has finished!");
-              });
+        let timer = sleep(self.config.max_delay);
+        tokio::pin!(timer);
+        loop {
+            tokio::select! {
+                // Clients wait while the mempool is full.
+                Some((transaction, replier)) = self.transaction_receiver.recv(), if !self.mempool.is_full() => {
+                    debug!("BlockBuilder received transaction {:?}", transaction);
+                    // The client waits for the commit only if this builder sees it.
+                    let waiting = self.commit_receiver.is_some().then(|| replier.clone());
+                    let admitted = self.mempool.admit(&transaction, waiting, self.epochs.borrow().last_committee(), Instant::now());
+                    match admitted {
+                        Ok(hash) => Self::reply(&replier, Receipt::Accepted(hash)),
+                        Err(e) => {
+                            debug!("BlockBuilder drops a transaction: {}", e);
+                            Self::reply(&replier, Receipt::Rejected(transaction_hash(&transaction), e.to_string()));
+                            continue;
+                        }
+                    }
+                    // The delay of a block runs from its first transaction.
+                    if self.current_transactions.is_empty() {
+                        timer.as_mut().reset(Instant::now() + self.config.max_delay);
+                    }
+                    self.current_bytes += transaction.len();
+                    self.current_transactions.push(transaction);
+
+                    if self.current_transactions.len() >= self.max_transactions() || self.current_bytes >= self.config.max_bytes {
+                        info!("BlockBuilder has enough transactions to make a block. Broadcast it to others");
+                        self.seal().await;
+                    }
+                },
+                () = &mut timer, if !self.current_transactions.is_empty() => {
+                    info!("BlockBuilder seals a block of {} transactions at the end of the batch delay", self.current_transactions.len());
+                    self.seal().await;
+                },
+                Some((block, position)) = Self::next_commit(&mut self.commit_receiver) => self.committed(block, position),
+                () = sleep_until(self.mempool.next_expiry().unwrap_or_else(Instant::now)), if self.mempool.is_full() => {
+                    self.mempool.expire(Instant::now());
+                },
+                else => break,
+            }
+        }
+    }
+
+    fn committed(&mut self, block: Block, position: CommitPosition) {
+        let now = Instant::now();
+        for transaction in &block.transactions {
+            if let (hash, Some(replier)) = self.mempool.commit(transaction, now) {
+                Self::reply(&replier, Receipt::Committed(hash, position));
+            }
+        }
+        if let Some(adaptive) = &mut self.adaptive {
+            adaptive.committed(&block.hash(), now);
+        }
+    }
+
+    async fn next_commit(
+        commit_receiver: &mut Option<Receiver<(Block, CommitPosition)>>,
+    ) -> Option<(Block, CommitPosition)> {
+        match commit_receiver {
+            Some(receiver) => receiver.recv().await,
+            None => None,
+        }
+    }
+
+    /// Receipts are dropped for the clients which do not read them fast enough.
+    fn reply(replier: &Replier, receipt: Receipt) {
+        let _ = replier.try_send(Bytes::from(receipt.to_bytes()));
+    }
+
+    fn max_transactions(&self) -> usize {
+        match &self.adaptive {
+            Some(adaptive) => adaptive.size(),
+            None => self.config.max_transactions,
+        }
+    }
+
+    async fn seal(&mut self) {
+        let block = Block::new(self.current_transactions.drain(..).collect());
+        self.current_bytes = 0;
+        let block_hash = block.hash();
+        if let Some(adaptive) = &mut self.adaptive {
+            adaptive.sealed(block_hash, Instant::now());
+        }
+        if self.commit_receiver.is_none() {
+            let now = Instant::now();
+            for transaction in &block.transactions {
+                self.mempool.commit(transaction, now);
             }
         }
+        if let Some(sender) = self.own_block_sender.clone() {
+            // The node waits for the builder to take every commit, so keep taking them while the
+            // consensus has no room for the block, or neither would ever move on.
+            let send = sender.send(block.clone());
+            tokio::pin!(send);
+            loop {
+                tokio::select! {
+                    result = &mut send => break result.expect("Failed to send block to propose"),
+                    Some((block, position)) = Self::next_commit(&mut self.commit_receiver) => self.committed(block, position),
+                }
+            }
+        }
+
+        debug!("{} transactions of the mempool wait to be committed", self.mempool.len());
+
+        // Broadcast the block through the network.
+        debug!("Broadcast Start!");
+        let mut handlers = match self.dissemination {
+            Dissemination::Full => self.send_block(block).await,
+            Dissemination::ErasureCoded => self.send_chunks(block).await,
+        };
+        if let Some(node) = self.node_address() {
+            let bytes = Bytes::from(Message::Batch(block_hash, true).to_bytes());
+            handlers.push(self.network.send(node, bytes).await);
+        }
+        
+        // for h in handlers {
+        //     if let Err(e) = h.await {
+        //         error!("Broadcast of the block was not successful: {:?}", e);
+        //     }
+        // }
+        // debug!("Broadcast has finished!");
+        tokio::spawn(async move {
+            for h in handlers {
+                if let Err(e) = h.await {
+                    error!("Broadcast of the block was not successful: {:?}", e);
+                }
+            }
+            debug!("Broadcast has finished!");
+        });
+    }
+
+    async fn send_block(&mut self, block: Block) -> Vec<CancelHandler> {
+        let bytes = Bytes::from(Message::Block(block).to_bytes());
+        self.network.broadcast(self.block_receiver_addresses(), bytes).await
+    }
+
+    /// Send the chunk of every receiver of the current committee, or the whole block if there are too
+    /// few of them to erasure code it.
+    async fn send_chunks(&mut self, block: Block) -> Vec<CancelHandler> {
+        let (epoch, receivers) = {
+            let epochs = self.epochs.borrow();
+            (epochs.current_epoch(), self.destination.receivers(epochs.current()))
+        };
+        let chunks = match shard_counts(receivers.len()) {
+            Some((data_shards, total_shards)) => Chunk::encode(&block, epoch, data_shards, total_shards),
+            None => return self.send_block(block).await,
+        };
+        let chunks = match chunks {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                warn!("Failed to erasure code a block, send it whole: {}", e);
+                return self.send_block(block).await;
+            }
+        };
+        let mut handlers = Vec::new();
+        for (chunk, (_, address)) in chunks.into_iter().zip(receivers) {
+            handlers.push(self.network.send(address, Bytes::from(Message::Chunk(chunk).to_bytes())).await);
+        }
+        handlers
+    }
+
+    fn block_receiver_addresses(&self) -> Vec<SocketAddr> {
+        let epochs = self.epochs.borrow();
+        let addresses: HashSet<_> = epochs
+            .current_and_next()
+            .flat_map(|committee| self.destination.receivers(committee))
+            .filter(|(id, _)| !matches!(self.destination, Destination::Nodes(node_id) if node_id == *id))
+            .map(|(_, address)| address)
+            .collect();
+        addresses.into_iter().collect()
+    }
+
+    /// Address of the node of a worker, which proposes the blocks of the worker.
+    fn node_address(&self) -> Option<SocketAddr> {
+        match self.destination {
+            Destination::Nodes(_) => None,
+            Destination::Workers(node_id, _) => self.epochs.borrow().current().get_node_address(node_id),
+        }
     }
 }
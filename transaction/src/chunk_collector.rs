@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use bytes::Bytes;
+use log::{debug, error, warn};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::watch;
+
+use model::block::Block;
+use model::chunk::{Chunk, MerkleRoot};
+use model::committee::Id;
+use model::epoch::Epochs;
+use model::message::Message;
+use model::Epoch;
+use network::{Authenticator, Emulator, ReliableSender};
+
+use crate::block_builder::Destination;
+
+/// Rebuilds the erasure-coded blocks of the other builders: it echoes the chunk of its own index to
+/// the other receivers, as it is the only one to get it from the builder, and hands every block to
+/// `block_sender` once it has enough chunks to rebuild it. Chunks are only kept for the current and
+/// the previous epochs.
+pub struct ChunkCollector {
+    node_id: Id,
+    /// Receivers the chunks of this node are echoed to, also giving the index of its chunks.
+    destination: Destination,
+    epochs: watch::Receiver<Epochs>,
+    chunk_receiver: Receiver<Chunk>,
+    block_sender: Sender<Block>,
+    /// Epoch the maps below were last pruned at.
+    epoch: Epoch,
+    /// Chunks of the blocks being rebuilt, by Merkle root.
+    chunks: HashMap<MerkleRoot, Vec<Chunk>>,
+    /// Blocks rebuilt or given up on, whose later chunks are dropped, with their epoch.
+    done: HashMap<MerkleRoot, Epoch>,
+    echoed: HashMap<MerkleRoot, Epoch>,
+    network: ReliableSender,
+}
+
+impl ChunkCollector {
+    pub fn spawn(
+        node_id: Id,
+        destination: Destination,
+        epochs: watch::Receiver<Epochs>,
+        chunk_receiver: Receiver<Chunk>,
+        block_sender: Sender<Block>,
+        emulator: Emulator,
+        authenticator: Option<Authenticator>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                node_id,
+                destination,
+                epochs,
+                chunk_receiver,
+                block_sender,
+                epoch: 0,
+                chunks: HashMap::new(),
+                done: HashMap::new(),
+                echoed: HashMap::new(),
+                network: ReliableSender::with_emulator(emulator).with_authenticator(authenticator),
+            }
+                .run()
+                .await;
+        });
+    }
+
+    async fn run(&mut self) {
+        while let Some(chunk) = self.chunk_receiver.recv().await {
+            self.prune();
+            if chunk.epoch + 1 < self.epoch {
+                debug!("Drop a chunk of epoch {}, which ended", chunk.epoch);
+                continue;
+            }
+            let receivers = match self.receivers(chunk.epoch) {
+                Some(receivers) => receivers,
+                None => {
+                    warn!("Drop a chunk of epoch {}, which is not scheduled", chunk.epoch);
+                    continue;
+                }
+            };
+            let node_count = receivers.len();
+            if !chunk.verify(node_count) {
+                warn!("Drop a chunk whose shard counts or proof do not match its epoch and root");
+                continue;
+            }
+            // The others may need the chunk even if this node has already rebuilt the block.
+            if !self.echoed.contains_key(&chunk.root) && self.is_own(&receivers, &chunk) {
+                self.echoed.insert(chunk.root, chunk.epoch);
+                self.echo(&receivers, &chunk).await;
+            }
+            if self.done.contains_key(&chunk.root) {
+                continue;
+            }
+
+            let root = chunk.root;
+            let chunks = self.chunks.entry(root).or_default();
+            if chunks.iter().any(|other| other.index == chunk.index) {
+                continue;
+            }
+            chunks.push(chunk);
+            if chunks.len() < chunks[0].data_shards as usize {
+                continue;
+            }
+            let chunks = self.chunks.remove(&root).unwrap();
+            self.done.insert(root, chunks[0].epoch);
+            match Chunk::decode(&chunks, node_count) {
+                Ok(block) => {
+                    debug!("Rebuilt a block with {} transactions from {} chunks", block.transactions.len(), chunks.len());
+                    self.block_sender.send(block).await.expect("Failed to send block");
+                }
+                Err(e) => warn!("Failed to rebuild a block from its chunks: {}", e),
+            }
+        }
+    }
+
+    /// Forget the blocks of the epochs before the previous one, once the epoch changes.
+    fn prune(&mut self) {
+        let epoch = self.epochs.borrow().current_epoch();
+        if epoch == self.epoch {
+            return;
+        }
+        self.epoch = epoch;
+        let kept = |block_epoch: &Epoch| block_epoch + 1 >= epoch;
+        self.chunks.retain(|_, chunks| kept(&chunks[0].epoch));
+        self.done.retain(|_, block_epoch| kept(block_epoch));
+        self.echoed.retain(|_, block_epoch| kept(block_epoch));
+    }
+
+    /// Receivers of the blocks built in `epoch`, whose number gives the shard counts and whose
+    /// order gives the index of their chunks.
+    fn receivers(&self, epoch: Epoch) -> Option<Vec<(Id, SocketAddr)>> {
+        let epochs = self.epochs.borrow();
+        epochs.committee_of(epoch).map(|committee| self.destination.receivers(committee))
+    }
+
+    /// Whether the chunk has the index of this node among its receivers.
+    fn is_own(&self, receivers: &[(Id, SocketAddr)], chunk: &Chunk) -> bool {
+        receivers.iter().position(|(id, _)| *id == self.node_id) == Some(chunk.index as usize)
+    }
+
+    async fn echo(&mut self, receivers: &[(Id, SocketAddr)], chunk: &Chunk) {
+        let addresses = receivers
+            .iter()
+            .filter(|(id, _)| *id != self.node_id)
+            .map(|(_, address)| *address)
+            .collect();
+        let bytes = Bytes::from(Message::Chunk(chunk.clone()).to_bytes());
+        let handlers = self.network.broadcast(addresses, bytes).await;
+        tokio::spawn(async move {
+            for h in handlers {
+                if let Err(e) = h.await {
+                    error!("Echo of a chunk was not successful: {:?}", e);
+                }
+            }
+        });
+    }
+}
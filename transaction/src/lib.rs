@@ -1,5 +1,13 @@
+mod batch;
 mod block_builder;
+mod chunk_collector;
+mod mempool;
 mod transaction_coordinator;
+mod worker;
 
 
+pub use crate::batch::BatchConfig;
+pub use crate::block_builder::Dissemination;
+pub use crate::mempool::{submit, MempoolConfig, MempoolError};
 pub use crate::transaction_coordinator::TransactionCoordinator;
+pub use crate::worker::{BatchCommitter, Worker};
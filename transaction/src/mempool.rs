@@ -0,0 +1,286 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use thiserror::Error;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::Sender;
+use tokio::time::Instant;
+
+use model::block::{transaction_hash, Transaction, TransactionHash};
+use model::committee::Committee;
+use model::epoch::Reconfiguration;
+use model::transaction::{Receipt, SignedTransaction};
+use network::{Replier, Writer};
+
+/// Limits of the mempool of a `BlockBuilder`.
+#[derive(Clone, Copy, Debug)]
+pub struct MempoolConfig {
+    /// Number of pending transactions beyond which the builder stops reading new ones, so the
+    /// clients wait.
+    pub max_transactions: usize,
+    /// Total size of the pending transactions beyond which the builder stops reading new ones.
+    pub max_bytes: usize,
+    /// Time after which a pending transaction is forgotten, so it may be submitted again, and a
+    /// committed one is no longer recognized.
+    pub ttl: Duration,
+    /// Whether to admit transactions which are not signed by their client, as they used to be.
+    pub accept_raw: bool,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self { max_transactions: 100_000, max_bytes: 50_000_000, ttl: Duration::from_secs(60), accept_raw: false }
+    }
+}
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum MempoolError {
+    #[error("the transaction is already pending")]
+    Pending,
+
+    #[error("the transaction is already committed")]
+    Committed,
+
+    #[error("the transaction is not signed")]
+    Unsigned,
+
+    #[error("the signature of the transaction is invalid")]
+    InvalidSignature,
+
+    #[error("the nonce of the transaction is not above the last one of its client")]
+    StaleNonce,
+
+    #[error("the reconfiguration is not signed by a quorum of the committee")]
+    UnauthorizedReconfiguration,
+}
+
+/// Hand a transaction of a client to its builder along with the replier of the connection, telling
+/// the client that the transaction is queued when the builder does not keep up.
+pub async fn submit(
+    transaction_sender: &Sender<(Transaction, Replier)>,
+    writer: &mut Writer,
+    transaction: Transaction,
+    replier: &Replier,
+) {
+    match transaction_sender.try_send((transaction, replier.clone())) {
+        Ok(()) => {}
+        Err(TrySendError::Full(submission)) => {
+            let _ = writer.send(Bytes::from(Receipt::Queued(transaction_hash(&submission.0)).to_bytes())).await;
+            transaction_sender.send(submission).await.expect("Failed to send transaction");
+        }
+        Err(TrySendError::Closed(_)) => panic!("Failed to send transaction"),
+    }
+}
+
+/// Transactions of a builder by hash: the pending ones, admitted but not committed yet, and the
+/// committed ones, so that a transaction submitted again is only batched once. Signed transactions
+/// must also have a nonce above the last one admitted or committed for their client, which is never
+/// forgotten, so they cannot be replayed after the TTL either.
+#[derive(Debug)]
+pub struct Mempool {
+    config: MempoolConfig,
+    /// Size and admission time of the pending transactions, and the connection of the client
+    /// waiting for their commit if any.
+    pending: HashMap<TransactionHash, (usize, Instant, Option<Replier>)>,
+    pending_bytes: usize,
+    committed: HashMap<TransactionHash, Instant>,
+    /// Transactions by admission or commit time, to expire them in order. Entries whose time no
+    /// longer matches the maps are stale and skipped.
+    pending_order: VecDeque<(Instant, TransactionHash)>,
+    committed_order: VecDeque<(Instant, TransactionHash)>,
+    /// Last nonce of every client, by public key.
+    nonces: HashMap<[u8; 32], u64>,
+}
+
+impl Mempool {
+    pub fn new(config: MempoolConfig) -> Self {
+        Self {
+            config,
+            pending: HashMap::new(),
+            pending_bytes: 0,
+            committed: HashMap::new(),
+            pending_order: VecDeque::new(),
+            committed_order: VecDeque::new(),
+            nonces: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.pending.len() >= self.config.max_transactions || self.pending_bytes >= self.config.max_bytes
+    }
+
+    /// Admit a transaction which is neither pending nor committed, and signed by its client unless
+    /// raw transactions are accepted. Reconfigurations must instead be signed by a quorum of
+    /// `committee`, the last scheduled one, as the consensus checks again once they are committed.
+    pub fn admit(
+        &mut self,
+        transaction: &Transaction,
+        replier: Option<Replier>,
+        committee: &Committee,
+        now: Instant,
+    ) -> Result<TransactionHash, MempoolError> {
+        self.expire(now);
+        let hash = transaction_hash(transaction);
+        if self.committed.contains_key(&hash) {
+            return Err(MempoolError::Committed);
+        }
+        if self.pending.contains_key(&hash) {
+            return Err(MempoolError::Pending);
+        }
+        match SignedTransaction::from_transaction(transaction) {
+            Some(signed) => {
+                if !signed.verify() {
+                    return Err(MempoolError::InvalidSignature);
+                }
+                if self.nonces.get(signed.client.as_bytes()).is_some_and(|last| signed.nonce <= *last) {
+                    return Err(MempoolError::StaleNonce);
+                }
+                self.nonces.insert(signed.client.to_bytes(), signed.nonce);
+            }
+            None => match Reconfiguration::from_transaction(transaction) {
+                Some(reconfiguration) if !reconfiguration.is_authorized(committee) => {
+                    return Err(MempoolError::UnauthorizedReconfiguration)
+                }
+                Some(_) => {}
+                None if self.config.accept_raw => {}
+                None => return Err(MempoolError::Unsigned),
+            },
+        }
+        self.pending.insert(hash, (transaction.len(), now, replier));
+        self.pending_bytes += transaction.len();
+        self.pending_order.push_back((now, hash));
+        Ok(hash)
+    }
+
+    /// Record a committed transaction, whether it was pending here or not, and return its hash and
+    /// the connection of the client waiting for it.
+    pub fn commit(&mut self, transaction: &Transaction, now: Instant) -> (TransactionHash, Option<Replier>) {
+        let hash = transaction_hash(transaction);
+        // The transactions admitted by other nodes are only trusted to raise the nonce of their
+        // client once their signature is checked.
+        if let Some(signed) = SignedTransaction::from_transaction(transaction) {
+            let last = self.nonces.get(signed.client.as_bytes()).copied();
+            if last.is_none_or(|last| signed.nonce > last) && signed.verify() {
+                self.nonces.insert(signed.client.to_bytes(), signed.nonce);
+            }
+        }
+        let replier = self.pending.remove(&hash).and_then(|(size, _, replier)| {
+            self.pending_bytes -= size;
+            replier
+        });
+        self.committed.insert(hash, now);
+        self.committed_order.push_back((now, hash));
+        (hash, replier)
+    }
+
+    /// Time at which the oldest transaction expires, if any.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        let pending = self.pending_order.front().map(|(time, _)| *time);
+        let committed = self.committed_order.front().map(|(time, _)| *time);
+        pending.into_iter().chain(committed).min().map(|time| time + self.config.ttl)
+    }
+
+    /// Forget the transactions older than the TTL.
+    pub fn expire(&mut self, now: Instant) {
+        while let Some((time, hash)) = self.pending_order.front().copied() {
+            if now.duration_since(time) < self.config.ttl {
+                break;
+            }
+            self.pending_order.pop_front();
+            if matches!(self.pending.get(&hash), Some((_, admitted, _)) if *admitted == time) {
+                let (size, ..) = self.pending.remove(&hash).unwrap();
+                self.pending_bytes -= size;
+            }
+        }
+        while let Some((time, hash)) = self.committed_order.front().copied() {
+            if now.duration_since(time) < self.config.ttl {
+                break;
+            }
+            self.committed_order.pop_front();
+            if self.committed.get(&hash) == Some(&time) {
+                self.committed.remove(&hash);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+
+    use super::*;
+
+    #[test]
+    fn test_mempool() {
+        let config = MempoolConfig { max_transactions: 2, max_bytes: 100, ttl: Duration::from_secs(10), accept_raw: true };
+        let mut mempool = Mempool::new(config);
+        let committee = Committee::generate_from_seed(4, 0);
+        let start = Instant::now();
+
+        mempool.admit(&vec![1; 10], None, &committee, start).unwrap();
+        assert_eq!(mempool.admit(&vec![1; 10], None, &committee, start), Err(MempoolError::Pending));
+        mempool.admit(&vec![2; 10], None, &committee, start).unwrap();
+        assert!(mempool.is_full());
+
+        // A committed transaction leaves room and is still rejected.
+        mempool.commit(&vec![1; 10], start + Duration::from_secs(5));
+        assert!(!mempool.is_full());
+        assert_eq!(mempool.admit(&vec![1; 10], None, &committee, start), Err(MempoolError::Committed));
+        assert!(mempool.admit(&vec![3; 100], None, &committee, start).is_ok());
+        assert!(mempool.is_full());
+        assert_eq!(mempool.next_expiry(), Some(start + Duration::from_secs(10)));
+
+        // Both pending and committed transactions are forgotten after the TTL.
+        mempool.expire(start + Duration::from_secs(10));
+        assert_eq!(mempool.len(), 0);
+        assert!(mempool.admit(&vec![2; 10], None, &committee, start + Duration::from_secs(12)).is_ok());
+        assert_eq!(mempool.admit(&vec![1; 10], None, &committee, start + Duration::from_secs(12)), Err(MempoolError::Committed));
+        assert!(mempool.admit(&vec![1; 10], None, &committee, start + Duration::from_secs(15)).is_ok());
+    }
+
+    #[test]
+    fn test_signed_transactions() {
+        let mut mempool = Mempool::new(MempoolConfig::default());
+        let committee = Committee::generate_from_seed(4, 0);
+        let now = Instant::now();
+        let secret = SecretKey::from_bytes(&[1; 32]).unwrap();
+        let keypair = Keypair { public: PublicKey::from(&secret), secret };
+
+        assert_eq!(mempool.admit(&vec![0; 9], None, &committee, now), Err(MempoolError::Unsigned));
+        mempool.admit(&SignedTransaction::new(&keypair, 2, vec![0; 9]).to_transaction(), None, &committee, now).unwrap();
+        let mut forged = SignedTransaction::new(&keypair, 3, vec![0; 9]);
+        forged.payload = vec![1; 9];
+        assert_eq!(mempool.admit(&forged.to_transaction(), None, &committee, now), Err(MempoolError::InvalidSignature));
+
+        // A nonce used with another payload is a replay, as are the nonces committed elsewhere.
+        let replayed = SignedTransaction::new(&keypair, 2, vec![1; 9]).to_transaction();
+        assert_eq!(mempool.admit(&replayed, None, &committee, now), Err(MempoolError::StaleNonce));
+        mempool.commit(&SignedTransaction::new(&keypair, 5, vec![0; 9]).to_transaction(), now);
+        let stale = SignedTransaction::new(&keypair, 4, vec![0; 9]).to_transaction();
+        assert_eq!(mempool.admit(&stale, None, &committee, now), Err(MempoolError::StaleNonce));
+        assert!(mempool.admit(&SignedTransaction::new(&keypair, 6, vec![0; 9]).to_transaction(), None, &committee, now).is_ok());
+    }
+
+    #[test]
+    fn test_reconfigurations() {
+        let mut mempool = Mempool::new(MempoolConfig { accept_raw: true, ..MempoolConfig::default() });
+        let now = Instant::now();
+        let (committee, keypairs) = Committee::generate_keys_from_seed(4, 0);
+        let mut reconfiguration = Reconfiguration::new(1, Committee::generate_from_seed(3, 1));
+        for id in 1..=2 {
+            reconfiguration.sign(&Keypair::from_bytes(&hex::decode(&keypairs[&id]).unwrap()).unwrap());
+        }
+
+        // Even with raw transactions, reconfigurations need the signatures of a quorum.
+        let unauthorized = reconfiguration.to_transaction();
+        assert_eq!(mempool.admit(&unauthorized, None, &committee, now), Err(MempoolError::UnauthorizedReconfiguration));
+        reconfiguration.sign(&Keypair::from_bytes(&hex::decode(&keypairs[&3]).unwrap()).unwrap());
+        assert!(mempool.admit(&reconfiguration.to_transaction(), None, &committee, now).is_ok());
+    }
+}
@@ -1,101 +1,64 @@
-use std::error::Error;
-
-use async_trait::async_trait;
-use bytes::Bytes;
-use futures::sink::SinkExt as _;
-use log::{debug, info, warn};
-use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc::{channel, Sender};
+use log::debug;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::watch;
 
 use model::block::{Block, Transaction};
-use model::committee::{Committee, Id};
-use model::DEFAULT_CHANNEL_CAPACITY;
-use network::{MessageHandler, Receiver, Writer};
-
-use crate::block_builder::BlockBuilder;
+use model::chunk::Chunk;
+use model::committee::Id;
+use model::epoch::Epochs;
+use model::transaction::CommitPosition;
+use network::{Authenticator, Emulator, Replier};
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum BlockMessage {
-    Block(Block),
-}
+use crate::batch::BatchConfig;
+use crate::block_builder::{BlockBuilder, Destination, Dissemination};
+use crate::chunk_collector::ChunkCollector;
+use crate::mempool::MempoolConfig;
 
 pub struct TransactionCoordinator;
 
 impl TransactionCoordinator {
+    /// Spawn the builder of the blocks of the transactions submitted to the node, which hands them to
+    /// `own_block_sender` to be proposed, and the collector rebuilding the erasure-coded blocks of
+    /// the other nodes into `block_sender`. The transactions,
+    /// the blocks and the chunks of the other nodes arrive on the port of the node, along with its
+    /// other messages. The committed blocks arrive on `commit_receiver`, which frees the mempool and
+    /// drives the size of the blocks in adaptive mode.
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         node_id: Id,
-        committee: Committee,
+        transaction_receiver: Receiver<(Transaction, Replier)>,
+        chunk_receiver: Receiver<Chunk>,
         block_sender: Sender<Block>,
-        batch_size: usize,
-        pretend_failure: bool,
+        own_block_sender: Sender<Block>,
+        commit_receiver: Receiver<(Block, CommitPosition)>,
+        batch: BatchConfig,
+        mempool: MempoolConfig,
+        dissemination: Dissemination,
+        emulator: Emulator,
+        epochs: watch::Receiver<Epochs>,
+        authenticator: Option<Authenticator>,
     ) {
-        let (transaction_to_block_builder_sender, transaction_receiver) = channel(DEFAULT_CHANNEL_CAPACITY);
-
-        let tx_address = committee.get_tx_receiver_address(node_id).unwrap();
-        debug!("Start listening for transactions on {:?}", tx_address);
-        Receiver::spawn(
-            tx_address,
-            TxReceiverHandler { transaction_to_block_builder_sender },
-            pretend_failure,
+        debug!("Start building blocks with {:?}, disseminated as {:?}", batch, dissemination);
+        ChunkCollector::spawn(
+            node_id,
+            Destination::Nodes(node_id),
+            epochs.clone(),
+            chunk_receiver,
+            block_sender,
+            emulator.clone(),
+            authenticator.clone(),
         );
-
-        let address = committee.get_block_receiver_address(node_id).unwrap();
-        debug!("Start listening for blocks on {:?}", address);
-        Receiver::spawn(
-            address,
-            BlockReceiverHandler { block_sender },
-            pretend_failure,
-        );
-
         BlockBuilder::spawn(
             transaction_receiver,
-            committee,
-            batch_size,
+            epochs,
+            Some(own_block_sender),
+            batch,
+            mempool,
+            Some(commit_receiver),
+            Destination::Nodes(node_id),
+            dissemination,
+            emulator,
+            authenticator,
         );
     }
 }
-
-#[derive(Clone)]
-struct TxReceiverHandler {
-    transaction_to_block_builder_sender: Sender<Transaction>,
-}
-
-#[async_trait]
-impl MessageHandler for TxReceiverHandler {
-    async fn dispatch(&self, _writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
-        debug!("TxReceiverHandler received transaction to process {:?}", message);
-        // Send the transaction to the block builder.
-        self.transaction_to_block_builder_sender
-            .send(message.to_vec())
-            .await
-            .expect("Failed to send transaction");
-
-        Ok(())
-    }
-}
-
-#[derive(Clone)]
-struct BlockReceiverHandler {
-    block_sender: Sender<Block>,
-}
-
-#[async_trait]
-impl MessageHandler for BlockReceiverHandler {
-    async fn dispatch(&self, writer: &mut Writer, serialized: Bytes) -> Result<(), Box<dyn Error>> {
-        // debug!("BlockReceiverHandler received the message and sends back the response");
-        let _ = writer.send(Bytes::from("Ack")).await;
-
-        match bincode::deserialize(&serialized) {
-            Ok(BlockMessage::Block(block)) => {
-                info!("BlockReceiverHandler received block to process with {} transactions and sends it to Consensus", block.transactions.len());
-                self
-                    .block_sender
-                    .send(block)
-                    .await
-                    .expect("Failed to send block")
-            },
-            Err(e) => warn!("Serialization error: {}", e),
-        }
-        Ok(())
-    }
-}
\ No newline at end of file
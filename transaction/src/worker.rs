@@ -0,0 +1,250 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use log::{debug, error, info, warn};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::{oneshot, watch};
+
+use model::block::{Block, BlockHash, Transaction};
+use model::chunk::Chunk;
+use model::committee::{Id, WorkerId};
+use model::epoch::Epochs;
+use model::message::Message;
+use model::transaction::CommitPosition;
+use model::DEFAULT_CHANNEL_CAPACITY;
+use network::{Authenticator, Emulator, MessageHandler, PeerId, Receiver as NetworkReceiver, ReliableSender, Replier, Writer};
+
+use crate::batch::BatchConfig;
+use crate::block_builder::{BlockBuilder, Destination, Dissemination};
+use crate::chunk_collector::ChunkCollector;
+use crate::mempool::{submit, MempoolConfig};
+
+/// Batches committed by the node, with the channel to reply with the blocks the worker stores.
+type Commit = (Vec<BlockHash>, CommitPosition, oneshot::Sender<Vec<Block>>);
+
+/// Worker of a node, usually in a process of its own: it batches the transactions of the clients into
+/// blocks sent to the workers of the same index of the other nodes, stores the blocks of these
+/// workers, and reports the digest of every block it stores to its node. The node tells it in turn
+/// which batches it committed, so its builder notifies the clients.
+pub struct Worker {
+    node_id: Id,
+    /// The node of the worker is found in the committee it started with.
+    epochs: watch::Receiver<Epochs>,
+    block_receiver: Receiver<Block>,
+    blocks: HashMap<BlockHash, Block>,
+    commit_receiver: Receiver<Commit>,
+    /// Committed blocks, for the builder of the worker.
+    commit_sender: Sender<(Block, CommitPosition)>,
+    network: ReliableSender,
+}
+
+impl Worker {
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        node_id: Id,
+        worker: WorkerId,
+        address: SocketAddr,
+        epochs: watch::Receiver<Epochs>,
+        batch: BatchConfig,
+        mempool: MempoolConfig,
+        dissemination: Dissemination,
+        authenticator: Option<Authenticator>,
+    ) {
+        let (transaction_sender, transaction_receiver) = channel(DEFAULT_CHANNEL_CAPACITY);
+        let (block_sender, block_receiver) = channel(DEFAULT_CHANNEL_CAPACITY);
+        let (chunk_sender, chunk_receiver) = channel(DEFAULT_CHANNEL_CAPACITY);
+        let (commit_sender, commit_receiver) = channel(DEFAULT_CHANNEL_CAPACITY);
+        let (builder_commit_sender, builder_commit_receiver) = channel(DEFAULT_CHANNEL_CAPACITY);
+
+        debug!("Start listening for transactions and blocks on {:?}", address);
+        NetworkReceiver::spawn_authenticated(
+            address,
+            WorkerHandler {
+                transaction_sender,
+                block_sender: block_sender.clone(),
+                chunk_sender,
+                commit_sender,
+                node_id,
+                authenticated: authenticator.is_some(),
+            },
+            Arc::new(AtomicBool::new(false)),
+            Emulator::default(),
+            authenticator.clone(),
+        );
+        info!("Worker {} of node {} listening to the messages on {}", worker, node_id, address);
+
+        ChunkCollector::spawn(
+            node_id,
+            Destination::Workers(node_id, worker),
+            epochs.clone(),
+            chunk_receiver,
+            block_sender,
+            Emulator::default(),
+            authenticator.clone(),
+        );
+        BlockBuilder::spawn(
+            transaction_receiver,
+            epochs.clone(),
+            None,
+            batch,
+            mempool,
+            Some(builder_commit_receiver),
+            Destination::Workers(node_id, worker),
+            dissemination,
+            Emulator::default(),
+            authenticator.clone(),
+        );
+
+        tokio::spawn(async move {
+            Self {
+                node_id,
+                epochs,
+                block_receiver,
+                blocks: HashMap::new(),
+                commit_receiver,
+                commit_sender: builder_commit_sender,
+                network: ReliableSender::new().with_authenticator(authenticator),
+            }
+                .run()
+                .await;
+        });
+    }
+
+    async fn run(&mut self) {
+        loop {
+            tokio::select! {
+                Some(block) = self.block_receiver.recv() => self.store(block).await,
+                Some((batches, position, reply)) = self.commit_receiver.recv() => {
+                    let blocks: Vec<Block> = batches.iter().filter_map(|hash| self.blocks.get(hash)).cloned().collect();
+                    for block in &blocks {
+                        self.commit_sender.send((block.clone(), position)).await.expect("Failed to send committed block");
+                    }
+                    let _ = reply.send(blocks);
+                },
+                else => break,
+            }
+        }
+    }
+
+    async fn store(&mut self, block: Block) {
+        // The node gets back the stored blocks as committed, so another worker must not be able to
+        // replace one, or to store other transactions under its digest.
+        if !block.verify() {
+            warn!("Drop a batch whose digest is not the one of its transactions");
+            return;
+        }
+        let block_hash = block.hash();
+        match self.blocks.entry(block_hash) {
+            Entry::Occupied(_) => return,
+            Entry::Vacant(entry) => entry.insert(block),
+        };
+        let node = match self.epochs.borrow().current().get_node_address(self.node_id) {
+            Some(node) => node,
+            None => {
+                warn!("Node {} is not in the committee, it cannot be told about its batches", self.node_id);
+                return;
+            }
+        };
+        let handler = self.network.send(node, Bytes::from(Message::Batch(block_hash, false).to_bytes())).await;
+        tokio::spawn(async move {
+            if let Err(e) = handler.await {
+                error!("Failed to report a batch to the node: {:?}", e);
+            }
+        });
+    }
+}
+
+/// Node side of the commits of the batches of its workers: it tells every worker of the node which
+/// batches the node committed and gets back the blocks of those the worker stores.
+pub struct BatchCommitter {
+    workers: Vec<SocketAddr>,
+    network: ReliableSender,
+}
+
+impl BatchCommitter {
+    pub fn new(workers: Vec<SocketAddr>, authenticator: Option<Authenticator>) -> Self {
+        Self { workers, network: ReliableSender::new().with_authenticator(authenticator) }
+    }
+
+    /// Send the batches committed at `position` to the workers, and return a future giving the
+    /// blocks of the batches once every worker replied.
+    pub async fn commit(&mut self, batches: Vec<BlockHash>, position: CommitPosition) -> impl Future<Output = Vec<Block>> {
+        let bytes = Bytes::from(Message::Committed(batches, position).to_bytes());
+        let handlers = self.network.broadcast(self.workers.clone(), bytes).await;
+        async move {
+            let mut blocks = vec![];
+            for handler in handlers {
+                match handler.await.map(|reply| bincode::deserialize::<Vec<Block>>(&reply)) {
+                    Ok(Ok(stored)) => blocks.extend(stored),
+                    Ok(Err(e)) => warn!("Invalid reply of a worker to committed batches: {}", e),
+                    Err(e) => error!("Failed to send committed batches to a worker: {:?}", e),
+                }
+            }
+            blocks
+        }
+    }
+}
+
+#[derive(Clone)]
+struct WorkerHandler {
+    transaction_sender: Sender<(Transaction, Replier)>,
+    block_sender: Sender<Block>,
+    chunk_sender: Sender<Chunk>,
+    commit_sender: Sender<Commit>,
+    node_id: Id,
+    /// Whether the worker authenticates its peers, so that only the workers of the nodes may send
+    /// blocks, and only its node may tell it about commits.
+    authenticated: bool,
+}
+
+#[async_trait]
+impl MessageHandler for WorkerHandler {
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        serialized: Bytes,
+        peer: Option<PeerId>,
+        replier: &Replier,
+    ) -> Result<(), Box<dyn Error>> {
+        match Message::from_bytes(&serialized)? {
+            Message::Transaction(transaction) => submit(&self.transaction_sender, writer, transaction, replier).await,
+            Message::Block(block) => {
+                let _ = writer.send(Bytes::from("Ack")).await;
+                if self.authenticated && peer.is_none() {
+                    warn!("Drop a block of an unauthenticated peer");
+                    return Ok(());
+                }
+                self.block_sender.send(block).await.expect("Failed to send block");
+            }
+            Message::Chunk(chunk) => {
+                let _ = writer.send(Bytes::from("Ack")).await;
+                if self.authenticated && peer.is_none() {
+                    warn!("Drop a chunk of an unauthenticated peer");
+                    return Ok(());
+                }
+                self.chunk_sender.send(chunk).await.expect("Failed to send chunk");
+            }
+            Message::Committed(batches, position) => {
+                let blocks = if self.authenticated && peer != Some(self.node_id) {
+                    warn!("Drop committed batches sent by another peer than node {}", self.node_id);
+                    vec![]
+                } else {
+                    let (sender, receiver) = oneshot::channel();
+                    self.commit_sender.send((batches, position, sender)).await.expect("Failed to send committed batches");
+                    receiver.await.expect("Failed to receive committed blocks")
+                };
+                writer.send(Bytes::from(bincode::serialize(&blocks)?)).await?;
+            }
+            _ => warn!("Drop a message which workers do not handle"),
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,3 @@
 #[macro_use]
 pub mod vertex_coordinator;
 pub mod vertex_broadcaster;
-mod vertex_message_handler;
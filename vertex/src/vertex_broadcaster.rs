@@ -1,51 +1,73 @@
+use std::net::SocketAddr;
+
 use bytes::Bytes;
 use log::{debug, error};
 use tokio::sync::mpsc::{Receiver};
+use tokio::sync::watch;
 
-use model::committee::Committee;
-use model::vertex::{Vertex};
+use model::committee::{Committee, Id};
+use model::epoch::Epochs;
+use model::message::Message;
+use model::vertex::VertexMessage;
 use network::ReliableSender;
 
 pub struct VertexBroadcaster {
-    vertex_to_broadcast_receiver: Receiver<Vertex>,
+    node_id: Id,
+    vertex_to_broadcast_receiver: Receiver<VertexMessage>,
     network: ReliableSender,
-    committee: Committee
+    /// Vertices go to the committee of their round.
+    epochs: watch::Receiver<Epochs>,
 }
 
 impl VertexBroadcaster {
-    pub fn spawn(vertex_to_broadcast_receiver: Receiver<Vertex>, network: ReliableSender, committee: Committee) {
+    pub fn spawn(node_id: Id, vertex_to_broadcast_receiver: Receiver<VertexMessage>, network: ReliableSender, epochs: watch::Receiver<Epochs>) {
         tokio::spawn(async move {
-            Self { vertex_to_broadcast_receiver, network, committee}.run().await;
+            Self { node_id, vertex_to_broadcast_receiver, network, epochs }.run().await;
         });
     }
 
     pub async fn run(&mut self) {
         loop {
-            match self.vertex_to_broadcast_receiver.recv().await.unwrap() {
-                vertex => {
-                    debug!("Vertex received for broadcast {}", vertex);
-                    let addresses = self
-                        .committee
-                        .get_node_addresses();
-                    let bytes = bincode::serialize(&vertex).expect("Failed to serialize vertex in VertexBroadcaster");
-
-                    let handlers = self.network.broadcast(addresses, Bytes::from(bytes)).await;
-
-                    tokio::spawn(async move {
-                      for h in handlers {
-                        if let Err(e) = h.await {
-                            error!("Broadcast of vertices was not successful")
-                        }
+            let message = self.vertex_to_broadcast_receiver.recv().await.unwrap();
+            debug!("Vertex received for broadcast {:?}", message);
+            let (addresses, bytes) = {
+                let epochs = self.epochs.borrow();
+                match message {
+                    VertexMessage::Broadcast(vertex) => (
+                        epochs.committee_at(vertex.round()).get_node_addresses(),
+                        Message::Vertex(vertex).to_bytes(),
+                    ),
+                    VertexMessage::Send(vertex, ids) => (
+                        Self::addresses_of(epochs.committee_at(vertex.round()), &ids),
+                        Message::Vertex(vertex).to_bytes(),
+                    ),
+                    // Sent as is, so they do not even decode as a message.
+                    VertexMessage::Malformed(bytes, ids) => (Self::addresses_of(epochs.current(), &ids), bytes),
+                    // Blocks are requested from and sent to nodes of the current or next committees.
+                    VertexMessage::RequestBlock(block_hash, id) => (
+                        Self::address_of(&epochs, id),
+                        Message::BlockRequest(block_hash, self.node_id).to_bytes(),
+                    ),
+                    VertexMessage::SendBlock(block, id) => (Self::address_of(&epochs, id), Message::BlockReply(block).to_bytes()),
+                }
+            };
+            let handlers = self.network.broadcast(addresses, Bytes::from(bytes)).await;
+
+            tokio::spawn(async move {
+                for h in handlers {
+                    if let Err(e) = h.await {
+                        error!("Broadcast of vertices was not successful: {}", e)
                     }
-                  });
-                    // for h in handlers {
-                    //     if let Err(e) = h.await {
-                    //         error!("Broadcast of vertices was not successful")
-                    //     }
-                    // }
                 }
-            }
+            });
         }
     }
 
+    fn address_of(epochs: &Epochs, id: Id) -> Vec<SocketAddr> {
+        epochs.current_and_next().find_map(|committee| committee.get_node_address(id)).into_iter().collect()
+    }
+
+    fn addresses_of(committee: &Committee, ids: &[Id]) -> Vec<SocketAddr> {
+        ids.iter().filter_map(|id| committee.get_node_address(*id)).collect()
+    }
 }
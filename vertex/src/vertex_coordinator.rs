@@ -1,37 +1,32 @@
-use log::{debug, info};
-use tokio::sync::mpsc::{Receiver, Sender};
+use log::debug;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::watch;
 
-use model::committee::{Committee, Id};
-use model::vertex::{Vertex};
-use network::{Receiver as NetworkReceiver, ReliableSender};
+use model::committee::Id;
+use model::epoch::Epochs;
+use model::vertex::VertexMessage;
+use network::{Authenticator, Emulator, ReliableSender};
 
 use crate::vertex_broadcaster::VertexBroadcaster;
-use crate::vertex_message_handler::VertexReceiverHandler;
 
 pub struct VertexCoordinator;
 
 impl VertexCoordinator {
+    /// Spawn the broadcaster of the vertices of the node and of the blocks it requests or serves. The
+    /// vertices of the other nodes arrive on the port of the node, along with its other messages.
     pub fn spawn(
         node_id: Id,
-        committee: Committee,
-        vertex_to_consensus_sender: Sender<Vertex>,
-        vertex_to_broadcast_receiver: Receiver<Vertex>,
-        pretend_failure: bool,
+        vertex_to_broadcast_receiver: Receiver<VertexMessage>,
+        emulator: Emulator,
+        epochs: watch::Receiver<Epochs>,
+        authenticator: Option<Authenticator>,
     ) {
-        // Spawn the network receiver listening to vertices broadcasted from the other nodes.
-        debug!("Start listening for vertices from other nodes");
-        let address = committee.get_node_address(node_id).unwrap();
-        NetworkReceiver::spawn(
-            address,
-            VertexReceiverHandler { vertex_to_consensus_sender },
-            pretend_failure,
-        );
-        info!("Vertex Coordinator listening to the messages on {}", address);
-
+        debug!("Start broadcasting vertices to the other nodes");
         VertexBroadcaster::spawn(
+            node_id,
             vertex_to_broadcast_receiver,
-            ReliableSender::new(),
-            committee
+            ReliableSender::with_emulator(emulator).with_authenticator(authenticator),
+            epochs
         );
     }
-}
\ No newline at end of file
+}